@@ -1,97 +1,322 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::config_type::{ImageKind, KnownImage};
+use crate::context::AnalysisContext;
+use crate::error::Error;
+use crate::freshener;
+use crate::graph::{Graph, GraphFormat};
+use crate::microtosca::MicroTosca;
+use crate::output::OutputFormat;
+use crate::report::FailOn;
+use crate::yaml_handler;
+
+/// KubeFreshener discovers the architectural smells of microservices
+/// by analyzing their deployment in Kubernetes
+#[derive(Debug, Parser)]
+#[command(name = "freshener", version)]
+pub struct Cli {
+    /// Folder containing the k8s manifests to analyze (can be repeated)
+    #[arg(long = "manifests", value_name = "DIR", default_value = "./manifests", global = true)]
+    pub manifests: Vec<PathBuf>,
+
+    /// Configuration file listing invoked services and smells to ignore
+    #[arg(long, value_name = "FILE", default_value = "./config.yaml", global = true)]
+    pub config: PathBuf,
+
+    /// Registry of the images known to be sidecars or message routers
+    #[arg(long, value_name = "FILE", default_value = "./known-images.yaml", global = true)]
+    pub known_images: PathBuf,
+
+    /// Skip the manifest files that cannot be parsed, with a warning, instead of failing
+    #[arg(long, global = true)]
+    pub skip_invalid: bool,
+
+    #[command(subcommand)]
+    pub command: CMD,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Subcommand)]
 pub enum CMD {
-    Analyze,
+    /// Analyze the manifests looking for architectural smells
+    Analyze(AnalyzeOptions),
+
+    /// Export the topology graph of the deployment, annotated with the smells found
+    ExportGraph {
+        /// Format of the exported graph
+        #[arg(long, value_enum, value_name = "FORMAT", default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+
+        /// File to write the graph to, instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Export the deployment as a microTOSCA topology
+    ExportMicrotosca {
+        /// File to write the topology to, instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+
+    /// Set the invoked services and the smells to ignore in the config
+    /// after the interactions of a microTOSCA topology
+    ImportMicrotosca {
+        model: PathBuf,
+    },
+
+    /// Split the manifest files declaring multiple k8s components
+    /// into a file per component, named <name>-<kind>.yaml
+    Split,
+
+    /// List the images known to be sidecars or message routers
     ListKnownImages,
+
+    /// List the manifests excluded from the analysis
+    #[command(name = "list-manifest-ignore")]
     ListManifestsIgnore,
-    AddKnownImage,
-    AddManifestIgnore,
-    DeleteKnownImage,
-    DeleteManifestIgnore,
-    NotExistingCommand  
+
+    /// Register an image as a known sidecar or message router
+    AddKnownImage {
+        name: String,
+        image: String,
+        #[arg(value_enum)]
+        kind: ImageKind,
+    },
+
+    /// Exclude a manifest file from the analysis
+    AddManifestIgnore {
+        filename: String,
+    },
+
+    /// Unregister a known image
+    DeleteKnownImage {
+        name: String,
+    },
+
+    /// Include again a manifest file in the analysis
+    DeleteManifestIgnore {
+        filename: String,
+    },
 }
 
-impl CMD {
+/// The options accepted by the analyze command
+#[derive(Debug, Args)]
+pub struct AnalyzeOptions {
+    /// Rewrite the manifests by applying the refactorings of the smells found
+    #[arg(short = 's', long = "fix")]
+    pub is_to_refactor: bool,
+
+    /// Show the refactorings as unified diffs, without applying them
+    #[arg(long, requires = "is_to_refactor")]
+    pub dry_run: bool,
 
-    #[allow(dead_code)]
-    fn get_types() -> Vec<String> {
-        vec!["sidecar", "mr"]
-        .into_iter()
-        .map(|k| k.to_owned())
-        .collect()
+    /// Write the refactorings to a patch file for `git apply`, without applying them
+    #[arg(long, value_name = "FILE", requires = "is_to_refactor")]
+    pub patch: Option<PathBuf>,
+
+    /// Write the refactored deployment to a fresh folder, leaving the manifests untouched
+    #[arg(long, value_name = "DIR", requires = "is_to_refactor")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Write the refactorings as a Kustomize overlay of the manifests, leaving them untouched
+    #[arg(long, value_name = "DIR", requires = "is_to_refactor")]
+    pub overlay: Option<PathBuf>,
+
+    /// Format of the produced report
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+    pub output_format: OutputFormat,
+
+    /// Smell or minimum severity making the analysis fail (can be repeated)
+    #[arg(long, value_name = "SMELL|SEVERITY", value_parser = parse_fail_on)]
+    pub fail_on: Vec<FailOn>,
+}
+
+fn parse_fail_on(s: &str) -> Result<FailOn, String> {
+    FailOn::from_str(s)
+        .ok_or_else(|| format!("must be one of {:?}", FailOn::names()))
+}
+
+pub fn split(cli: &Cli) -> Result<bool, Error> {
+    let config = yaml_handler::get_config(&cli.config)?;
+    let created = yaml_handler::split_manifests(
+        &cli.manifests,
+        &config.ignored_manifests,
+        cli.skip_invalid
+    )?;
+
+    if created.is_empty() {
+        println!("[*] No manifest declares multiple k8s components");
     }
 
-    pub fn from_str(s: &str) -> Self {
-        match s {
-            "analyze" => Self::Analyze,
-            "list-known-images" => Self::ListKnownImages,
-            "list-manifest-ignore" => Self::ListManifestsIgnore,
-            "add-known-image" => Self::AddKnownImage,
-            "add-manifest-ignore" => Self::AddManifestIgnore,
-            "delete-known-image" => Self::DeleteKnownImage,
-            "delete-manifest-ignore" => Self::DeleteManifestIgnore,
-            _ => Self::NotExistingCommand
-        }
+    for path in created {
+        println!("[*] Created {}", path.display());
     }
 
-    #[allow(dead_code)]
-    pub fn to_str(&self) -> &str {
-        match self {
-            Self::Analyze => "analyze",
-            Self::ListKnownImages => "list-known-images",
-            Self::ListManifestsIgnore => "list-manifest-ignore",
-            Self::AddKnownImage => "add-known-image",
-            Self::AddManifestIgnore => "add-manifest-ignore",
-            Self::DeleteKnownImage => "delete-known-image",
-            Self::DeleteManifestIgnore => "delete-manifest-ignore",
-            _ => ""
-        }
+    Ok(true)
+}
+
+pub fn export_graph(cli: &Cli, format: GraphFormat, output: Option<&Path>) -> Result<bool, Error> {
+    let ctx = AnalysisContext::load(
+        &cli.manifests,
+        &cli.config,
+        &cli.known_images,
+        cli.skip_invalid,
+        false
+    )?;
+    let report = freshener::check_all(&ctx);
+    let graph = Graph::build(&ctx, &report).render(format);
+
+    match output {
+        Some(path) => {
+            fs::write(path, graph).map_err(|e| Error::io(path, e))?;
+            println!("[*] Graph written to {}", path.display());
+        },
+        None => print!("{}", graph),
+    }
+
+    Ok(true)
+}
+
+pub fn export_microtosca(cli: &Cli, output: Option<&Path>) -> Result<bool, Error> {
+    let ctx = AnalysisContext::load(
+        &cli.manifests,
+        &cli.config,
+        &cli.known_images,
+        cli.skip_invalid,
+        false
+    )?;
+    let model = MicroTosca::from_deployment(&ctx);
+
+    match output {
+        Some(path) => {
+            yaml_handler::save_microtosca(path, &model)?;
+            println!("[*] microTOSCA topology written to {}", path.display());
+        },
+        None => {
+            let yaml = serde_yaml::to_string(&model).map_err(|e| Error::serialize(Path::new("<stdout>"), e))?;
+            print!("{}", yaml);
+        },
+    }
+
+    Ok(true)
+}
+
+pub fn import_microtosca(cli: &Cli, model: &Path) -> Result<bool, Error> {
+    let mut config = yaml_handler::get_config(&cli.config)?;
+    let model = yaml_handler::get_microtosca(model)?;
+
+    model.update_config(&mut config);
+    yaml_handler::save_config(&cli.config, &config)?;
+
+    let invoked = config.invoked_services.unwrap_or_default();
+    println!("[*] Invoked services: {}", invoked.join(", "));
+    println!("[*] Ignored wobbly interactions: {}", config.ignore_smells.wobbly.join(", "));
+    println!(
+        "[*] Ignored endpoint based interactions: {}",
+        config.ignore_smells.endpoint_based_interaction.join(", ")
+    );
+
+    Ok(true)
+}
+
+pub fn list_known_images(cli: &Cli) -> Result<bool, Error> {
+    let images = yaml_handler::get_known_images(&cli.known_images)?;
+
+    if images.is_empty() {
+        println!("[*] No known image registered in {}", cli.known_images.display());
+    }
+
+    for known in images {
+        println!("{} => {} ({})", known.name, known.image, kind_to_str(known.kind));
     }
 
-    #[allow(dead_code)]
-    pub fn check_args(&self, args: &Vec<String>) -> bool {
-        match self {
-            Self::AddKnownImage => {
-                if args.len() < 5 {
-                    println!("[X] You're missing parameters: [cargo run {} <name> <image> <kind>]",
-                        Self::AddKnownImage.to_str()
-                    );
-                    return false;
-                }
-
-                if !Self::get_types().into_iter().any(|k| k == args[4]) {
-                    println!("[X] <kind> parameter must be: {:?}", Self::get_types());
-                    return false;
-                }
-
-                true
-            },
-            Self::AddManifestIgnore => {
-                if args.len() < 3 {
-                    println!("[X] You're missing parameters: [cargo run {} <filename>]",
-                        Self::AddManifestIgnore.to_str(),
-                    );
-                    return false;
-                }
-                true
-            },
-            Self::DeleteKnownImage => {
-                if args.len() < 3 {
-                        println!("[X] You're missing <name> parameter: [cargo run {} <name>]",
-                        Self::DeleteKnownImage.to_str()
-                    );
-                    return false;
-                }
-                true
-            },
-            Self::DeleteManifestIgnore => {
-                if args.len() < 3 {
-                        println!("[X] You're missing <name> parameter: [cargo run {} <name>]",
-                        Self::DeleteKnownImage.to_str()
-                    );
-                    return false;
-                }
-                true
-            }
-            _ => true
+    Ok(true)
+}
+
+pub fn add_known_image(cli: &Cli, name: &str, image: &str, kind: ImageKind) -> Result<bool, Error> {
+    let mut images = yaml_handler::get_known_images(&cli.known_images)?;
+    let known = KnownImage { name: name.to_owned(), image: image.to_owned(), kind };
+
+    match images.iter_mut().find(|k| k.name == name) {
+        Some(existing) => {
+            *existing = known;
+            println!("[*] Known image {} updated", name);
+        },
+        None => {
+            images.push(known);
+            println!("[*] Known image {} added", name);
         }
     }
-}
\ No newline at end of file
+
+    yaml_handler::save_known_images(&cli.known_images, &images)?;
+    Ok(true)
+}
+
+pub fn delete_known_image(cli: &Cli, name: &str) -> Result<bool, Error> {
+    let mut images = yaml_handler::get_known_images(&cli.known_images)?;
+    let before = images.len();
+    images.retain(|k| k.name != name);
+
+    if images.len() == before {
+        println!("[X] There's no known image named {}", name);
+        return Ok(false);
+    }
+
+    yaml_handler::save_known_images(&cli.known_images, &images)?;
+    println!("[*] Known image {} deleted", name);
+    Ok(true)
+}
+
+pub fn list_manifest_ignore(cli: &Cli) -> Result<bool, Error> {
+    let config = yaml_handler::get_config(&cli.config)?;
+
+    if config.ignored_manifests.is_empty() {
+        println!("[*] No manifest is ignored");
+    }
+
+    for filename in config.ignored_manifests {
+        println!("{}", filename);
+    }
+
+    Ok(true)
+}
+
+pub fn add_manifest_ignore(cli: &Cli, filename: &str) -> Result<bool, Error> {
+    let mut config = yaml_handler::get_config(&cli.config)?;
+
+    if config.ignored_manifests.iter().any(|f| f == filename) {
+        println!("[*] {} is already ignored", filename);
+        return Ok(true);
+    }
+
+    config.ignored_manifests.push(filename.to_owned());
+    yaml_handler::save_ignored_manifests(&cli.config, &config.ignored_manifests)?;
+    println!("[*] {} will be ignored", filename);
+    Ok(true)
+}
+
+pub fn delete_manifest_ignore(cli: &Cli, filename: &str) -> Result<bool, Error> {
+    let mut config = yaml_handler::get_config(&cli.config)?;
+    let before = config.ignored_manifests.len();
+    config.ignored_manifests.retain(|f| f != filename);
+
+    if config.ignored_manifests.len() == before {
+        println!("[X] {} is not ignored", filename);
+        return Ok(false);
+    }
+
+    yaml_handler::save_ignored_manifests(&cli.config, &config.ignored_manifests)?;
+    println!("[*] {} will be analyzed again", filename);
+    Ok(true)
+}
+
+fn kind_to_str(kind: ImageKind) -> &'static str {
+    match kind {
+        ImageKind::Sidecar => "sidecar",
+        ImageKind::Mr => "message router",
+    }
+}
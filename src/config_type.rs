@@ -1,22 +1,79 @@
-use serde::{Deserialize};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize)]
+use crate::k8s_types::DEFAULT_NAMESPACE;
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NodeConfigElement {
     pub name: String,
     pub containers: Option<Vec<String>>
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Smells {
     pub multiple_container: Vec<NodeConfigElement>,
     pub noapigateway: Vec<NodeConfigElement>,
     pub endpoint_based_interaction: Vec<String>,
-    pub wobbly: Vec<String>
+    pub wobbly: Vec<String>,
+    /// the services whose target ports are not to be checked
+    #[serde(default)]
+    pub unmatched_target_port: Vec<String>,
+    /// the persistent volume claims and the datastores that may be shared
+    #[serde(default)]
+    pub shared_persistence: Vec<String>,
+    /// the workloads and the ConfigMaps whose endpoints are not to be checked
+    #[serde(default)]
+    pub hardcoded_endpoint: Vec<String>
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub ignore_smells: Smells,
-    pub invoked_services: Vec<String>,
-    pub ignored_manifests: Vec<String>
-}
\ No newline at end of file
+
+    /// the invoked services, which are inferred from the manifests if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoked_services: Option<Vec<String>>,
+
+    pub ignored_manifests: Vec<String>,
+
+    /// the namespace of the objects declared without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_namespace: Option<String>
+}
+
+impl Config {
+    pub fn default_namespace(&self) -> &str {
+        self.default_namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE)
+    }
+}
+
+/// The kinds of images that can be registered as known
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageKind {
+    Sidecar,
+    /// message router
+    Mr,
+}
+
+/// An image known to implement a sidecar or a message router
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownImage {
+    pub name: String,
+    pub image: String,
+    pub kind: ImageKind
+}
+
+impl KnownImage {
+    /// It tells whether a container image is this known image. Images registered
+    /// without a tag (or digest) match whatever tag of the same repository.
+    pub fn matches(&self, image: &str) -> bool {
+        if image == self.image {
+            return true;
+        }
+
+        image
+            .strip_prefix(&self.image)
+            .is_some_and(|rest| rest.starts_with(':') || rest.starts_with('@'))
+    }
+}
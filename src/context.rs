@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config_type::{Config, ImageKind, KnownImage};
+use crate::error::Error;
+use crate::inference;
+use crate::k8s_types::*;
+use crate::yaml_handler;
+
+/// It gathers everything the smell checks work on: the manifests parsed
+/// from disk, the configuration and a few indexes built once on top of them
+pub struct AnalysisContext {
+    /// the folders the manifests have been read from
+    pub manifest_dirs: Vec<PathBuf>,
+    pub manifests: Vec<K8SManifest>,
+    pub config: Config,
+    pub known_images: Vec<KnownImage>,
+    by_kind: HashMap<String, Vec<usize>>,
+    by_name: HashMap<String, Vec<usize>>,
+    by_label: HashMap<(String, String), Vec<usize>>,
+    /// the invoked services, as named in the config (or after their inference)
+    invoked: Vec<(String, Host)>,
+}
+
+impl AnalysisContext {
+    /// It parses the manifests and reads the config, both exactly once
+    pub fn load(
+        manifest_dirs: &[PathBuf],
+        config_path: &Path,
+        known_images_path: &Path,
+        skip_invalid: bool,
+        log: bool
+    ) -> Result<Self, Error> {
+        let config = yaml_handler::get_config(config_path)?;
+        let known_images = yaml_handler::get_known_images(known_images_path)?;
+        let manifests = yaml_handler::parse_manifests(
+            manifest_dirs,
+            &config.ignored_manifests,
+            skip_invalid,
+            log
+        )?;
+
+        let ctx = Self::new(manifest_dirs.to_vec(), manifests, config, known_images);
+
+        if log && ctx.config.invoked_services.is_none() {
+            let names: Vec<&str> = ctx.invoked.iter().map(|(name, _)| name.as_str()).collect();
+            println!("[*] Inferred invoked services: {}", names.join(", "));
+        }
+
+        Ok(ctx)
+    }
+
+    pub fn new(
+        manifest_dirs: Vec<PathBuf>,
+        manifests: Vec<K8SManifest>,
+        config: Config,
+        known_images: Vec<KnownImage>
+    ) -> Self {
+        let mut by_kind: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut by_label: HashMap<(String, String), Vec<usize>> = HashMap::new();
+
+        for (i, manifest) in manifests.iter().enumerate() {
+            by_kind.entry(manifest.kind.clone()).or_default().push(i);
+            if !manifest.metadata.name.is_empty() {
+                by_name.entry(manifest.metadata.name.clone()).or_default().push(i);
+            }
+
+            // labels are indexed both from the object metadata and from the
+            // pods it runs, so that workloads can be found by either of them
+            let pod_labels = manifest.is_workload().then(|| manifest.pod_labels());
+
+            for labels in manifest.metadata.labels.iter().chain(pod_labels.as_ref()) {
+                for (key, value) in labels.pairs() {
+                    let indexes = by_label.entry((key.to_owned(), value.to_owned())).or_default();
+                    if !indexes.contains(&i) {
+                        indexes.push(i);
+                    }
+                }
+            }
+        }
+
+        let mut ctx = AnalysisContext {
+            manifest_dirs,
+            manifests,
+            config,
+            known_images,
+            by_kind,
+            by_name,
+            by_label,
+            invoked: vec![],
+        };
+
+        // the invoked services listed in the config override the inferred ones,
+        // where the names given without a namespace refer to the default one
+        let default_namespace = ctx.config.default_namespace().to_owned();
+        ctx.invoked = match &ctx.config.invoked_services {
+            Some(listed) => listed
+                .iter()
+                .map(|s| (s.clone(), Host::resolve(s, &default_namespace)))
+                .collect(),
+            None => inference::invoked_services(&ctx)
+                .into_iter()
+                .map(|host| match host.namespace == default_namespace {
+                    true => (host.name.clone(), host),
+                    false => (host.to_string(), host),
+                })
+                .collect(),
+        };
+
+        ctx
+    }
+
+    fn resolve(&self, indexes: Option<&Vec<usize>>) -> Vec<&K8SManifest> {
+        indexes
+            .map(|idx| idx.iter().map(|i| &self.manifests[*i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// It returns the manifests whose kind is one of `kinds`, in parsing order
+    pub fn of_kind(&self, kinds: &[&str]) -> Vec<&K8SManifest> {
+        let mut indexes: Vec<usize> = kinds
+            .iter()
+            .filter_map(|k| self.by_kind.get(*k))
+            .flatten()
+            .copied()
+            .collect();
+        indexes.sort_unstable();
+
+        indexes.into_iter().map(|i| &self.manifests[i]).collect()
+    }
+
+    /// It returns the manifests named `name`, whatever their kind
+    pub fn named(&self, name: &str) -> Vec<&K8SManifest> {
+        self.resolve(self.by_name.get(name))
+    }
+
+    /// It returns the manifests carrying the label `key: value`
+    pub fn labelled(&self, key: &str, value: &str) -> Vec<&K8SManifest> {
+        self.resolve(self.by_label.get(&(key.to_owned(), value.to_owned())))
+    }
+
+    /// It returns the manifest declared by the given document of a file
+    pub fn declared_at(&self, file: &str, document: usize) -> Option<&K8SManifest> {
+        self.manifests
+            .iter()
+            .find(|m| m.source.as_ref().is_some_and(|s| s.is_at(file, document)))
+    }
+
+    /// It returns the workloads whose pods are selected by a service, i.e., those
+    /// in the namespace of the service whose pods carry all the selector labels
+    pub fn selected_by(&self, service: &K8SManifest) -> Vec<&K8SManifest> {
+        let Some(selector) = service.service_selector() else { return vec![] };
+        let Some((key, value)) = selector.pairs().first().copied() else { return vec![] };
+
+        self.labelled(key, value)
+            .into_iter()
+            .filter(|m| m.is_workload() && self.namespace_of(m) == self.namespace_of(service))
+            .filter(|m| m.pod_labels().contains_all(&selector))
+            .collect()
+    }
+
+    /// It returns the services selecting the pods of a workload
+    pub fn services_selecting(&self, workload: &K8SManifest) -> Vec<&K8SManifest> {
+        self.services()
+            .into_iter()
+            .filter(|s| self.selected_by(s).iter().any(|w| std::ptr::eq(*w, workload)))
+            .collect()
+    }
+
+    /// It tells whether `image` is registered as a known image of the given kind
+    pub fn is_known_image(&self, image: &str, kind: ImageKind) -> bool {
+        self.known_images
+            .iter()
+            .any(|k| k.kind == kind && k.matches(image))
+    }
+
+    /// It returns the folder where newly generated manifests are placed
+    pub fn output_dir(&self) -> &Path {
+        &self.manifest_dirs[0]
+    }
+
+    /// It filters the workloads (pods, deployments, stateful sets, daemon sets,
+    /// replica sets, jobs and cron jobs) from all the manifests declared
+    pub fn workloads(&self) -> Vec<&K8SManifest> {
+        self.of_kind(&WORKLOAD_KINDS)
+    }
+
+    /// It filters services from all the manifests declared
+    pub fn services(&self) -> Vec<&K8SManifest> {
+        self.of_kind(&["Service"])
+    }
+
+    /// It filters virtual services from all the manifests declared
+    pub fn virtual_services(&self) -> Vec<&K8SManifest> {
+        self.of_kind(&["VirtualService"])
+    }
+
+    /// It returns the hosts the traffic to `host` can be sent to, i.e., `host`
+    /// itself and the k8s services selecting the workload reached at `host`
+    pub fn routing_hosts(&self, host: &Host) -> Vec<Host> {
+        let mut hosts = vec![host.clone()];
+
+        if let Some(workload) = self.workload_for(host) {
+            for service in self.services_selecting(workload) {
+                let service_host = Host {
+                    name: service.metadata.name.clone(),
+                    namespace: self.namespace_of(service).to_owned(),
+                };
+
+                if !hosts.contains(&service_host) {
+                    hosts.push(service_host);
+                }
+            }
+        }
+
+        hosts
+    }
+
+    /// It returns the host the traffic to `host` is to be routed through,
+    /// i.e., the first k8s service selecting its workload (if any)
+    pub fn service_host_for(&self, host: &Host) -> Host {
+        let hosts = self.routing_hosts(host);
+        hosts.get(1).unwrap_or(host).clone()
+    }
+
+    /// It returns the virtual services routing the traffic sent to `host`,
+    /// either directly or through the k8s services selecting its workload
+    pub fn virtual_services_for(&self, host: &Host) -> Vec<&K8SManifest> {
+        let hosts = self.routing_hosts(host);
+
+        self.virtual_services()
+            .into_iter()
+            .filter(|m| m.spec.hosts.iter().flatten().any(|h| hosts.contains(&self.host_from(h, m))))
+            .collect()
+    }
+
+    /// It tells whether a destination rule sets an outlier detection (i.e.,
+    /// a circuit breaker) on the traffic sent to `host`, either directly or
+    /// through the k8s services selecting its workload
+    pub fn has_circuit_breaker(&self, host: &Host) -> bool {
+        let hosts = self.routing_hosts(host);
+
+        self.destination_rules()
+            .iter()
+            .any(|m| {
+                let has_outlier_detection = m.spec.trafficPolicy
+                    .as_ref()
+                    .is_some_and(|t| t.outlier_detection.is_some());
+
+                has_outlier_detection && m.spec.host.as_ref().is_some_and(|h| hosts.contains(&self.host_from(h, m)))
+            })
+    }
+
+    /// It filters destination rules from all the manifests declared
+    pub fn destination_rules(&self) -> Vec<&K8SManifest> {
+        self.of_kind(&["DestinationRule"])
+    }
+
+    /// It returns the namespace of a manifest, i.e., the default one if it declares none
+    pub fn namespace_of<'a>(&'a self, manifest: &'a K8SManifest) -> &'a str {
+        manifest.metadata.namespace
+            .as_deref()
+            .unwrap_or(self.config.default_namespace())
+    }
+
+    /// It resolves a host name as reached from the objects of `manifest`
+    pub fn host_from(&self, host: &str, manifest: &K8SManifest) -> Host {
+        Host::resolve(host, self.namespace_of(manifest))
+    }
+
+    /// It returns the invoked services, together with the names they are known by
+    pub fn invoked_services(&self) -> Vec<(&str, Host)> {
+        self.invoked
+            .iter()
+            .map(|(name, host)| (name.as_str(), host.clone()))
+            .collect()
+    }
+
+    /// It returns the namespace to declare in the objects generated for `host`,
+    /// i.e., none if they belong to the default namespace
+    pub fn namespace_for(&self, host: &Host) -> Option<String> {
+        (host.namespace != self.config.default_namespace()).then(|| host.namespace.clone())
+    }
+
+    /// It returns the workload of the microservice reached at `host`, whatever its kind
+    pub fn workload_for(&self, host: &Host) -> Option<&K8SManifest> {
+        self.named(&host.name)
+            .into_iter()
+            .find(|m| m.is_workload() && self.namespace_of(m) == host.namespace)
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The failures that prevent KubeFreshener from completing its job
+#[derive(Debug)]
+pub enum Error {
+    /// a file cannot be read or written
+    Io { path: PathBuf, source: io::Error },
+    /// a file is not valid YAML, or it does not declare what is expected
+    Parse {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    /// an object cannot be turned into YAML
+    Serialize { path: PathBuf, message: String },
+}
+
+impl Error {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io { path: path.to_path_buf(), source }
+    }
+
+    /// It wraps a serde_yaml error, moving its location (if any) to the diagnostic
+    pub fn parse(path: &Path, error: serde_yaml::Error) -> Self {
+        let mut message = error.to_string();
+        let location = error.location();
+
+        if let Some(location) = &location {
+            let position = format!(" at line {} column {}", location.line(), location.column());
+            message = message.replacen(&position, "", 1);
+        }
+
+        Error::Parse {
+            path: path.to_path_buf(),
+            line: location.as_ref().map(|l| l.line()),
+            column: location.as_ref().map(|l| l.column()),
+            message,
+        }
+    }
+
+    /// It wraps a serde_yaml error that carries no location, locating it at `line`
+    pub fn parse_at(path: &Path, line: usize, error: serde_yaml::Error) -> Self {
+        Error::Parse {
+            path: path.to_path_buf(),
+            line: Some(line),
+            column: None,
+            message: error.to_string(),
+        }
+    }
+
+    pub fn serialize(path: &Path, error: serde_yaml::Error) -> Self {
+        Error::Serialize { path: path.to_path_buf(), message: error.to_string() }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Parse { path, line, column, message } => {
+                write!(f, "{}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                }
+                if let Some(column) = column {
+                    write!(f, ":{}", column)?;
+                }
+                write!(f, ": {}", message)
+            },
+            Error::Serialize { path, message } =>
+                write!(f, "{}: cannot serialize: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,83 +1,86 @@
-use std::{collections::HashMap};
-
-use colored::Colorize;
+use std::{collections::{BTreeMap, HashMap}};
 
 use crate::{k8s_types::*, yaml_handler};
+use crate::config_type::ImageKind;
+use crate::context::AnalysisContext;
+use crate::graph::{self, NodeKind};
+use crate::inference;
+use crate::report::{Finding, Fix, ManifestRef, Report, Severity, Smell};
+
+/// It runs all the smell checks on the deployment
+pub fn check_all(ctx: &AnalysisContext) -> Report {
+    let mut report = Report::default();
+    report.extend(check_independent_depl(ctx));
+    report.extend(check_no_apigateway(ctx));
+    report.extend(check_endpoint_based_interaction(ctx));
+    report.extend(check_wobbly_interaction(ctx));
+    report.extend(check_unmatched_target_port(ctx));
+    report.extend(check_shared_persistence(ctx));
+    report.extend(check_hardcoded_endpoints(ctx));
+    report
+}
 
-pub fn check_wobbly_interaction(
-    manifests: &Vec<K8SManifest>,
-    is_to_refactor: bool
-) {
-    let ref virtual_services = yaml_handler::get_virtual_services(manifests);
-    let ref dest_rules = yaml_handler::get_destination_rules(manifests);
-    let config = yaml_handler::get_config();
+pub fn check_wobbly_interaction(ctx: &AnalysisContext) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+    let config = &ctx.config;
 
-    for invoked_service in &config.invoked_services[..] {        
+    for (invoked_service, invoked_host) in ctx.invoked_services() {
+        if config.ignore_smells.wobbly.iter().any(|s| s == invoked_service) { continue }
         // given the destination node I have to check if there is a virtual service
         // having spec.hosts = dest_node_name or a destination rule having
-        // spec.host = dest_node_n
-        let has_virtual_service = virtual_services
-            .into_iter()
-            .any(|m| {
-                if let Some(hosts) = &m.spec.hosts {
-                    return hosts
-                    .into_iter()
-                    .any(|h| &*h == invoked_service)
-                }
-
-                false
-            });
-
-        let has_outlier_detection = dest_rules
-            .into_iter()
-            .any(|m| {
-                if let (Some(host), Some(traffic_policy)) = (&m.spec.host, &m.spec.trafficPolicy) {
-                    return &*host == invoked_service && !traffic_policy.outlier_detection.is_none()
-                }
-
-                false
-            });
+        // spec.host = dest_node_n, where hosts are resolved from their namespace
+        let has_virtual_service = !ctx.virtual_services_for(&invoked_host).is_empty();
+        let has_outlier_detection = ctx.has_circuit_breaker(&invoked_host);
 
         if !has_virtual_service && !has_outlier_detection {
-            println!(
-                "{}\n(*) Service named {} is reached by another service \n\
-                without any circuit breaker or timeout. \n\
-                {} solve it by adding circuit_breaker and/or and timeout in between .\n",
-                format!("! [Wobbly Interaction]").red().bold(),
-                format!("{}", invoked_service).cyan().bold(),
-                format!("\nHint:").yellow().italic(),
-            );
+            // the virtual service is for the host the service is actually called at
+            let routed_host = ctx.service_host_for(&invoked_host);
+
+            let mut finding = Finding::new(
+                Smell::WobblyInteraction,
+                Severity::Warning,
+                format!(
+                    "Service named {} is reached by another service \
+                    without any circuit breaker or timeout.",
+                    invoked_service
+                )
+            )
+            .with_service(invoked_service)
+            .with_hint("solve it by adding circuit_breaker and/or timeout in between.")
+            .with_fix(Fix::CreateVirtualService {
+                host: routed_host.name.clone(),
+                namespace: ctx.namespace_for(&routed_host),
+            });
 
-            if is_to_refactor {
-                yaml_handler::create_virtual_service(invoked_service.clone());
+            if let Some(deployment) = ctx.workload_for(&invoked_host) {
+                finding = finding.on_manifest(deployment);
             }
+
+            findings.push(finding);
         }
     }
+
+    findings
 }
 
-pub fn check_endpoint_based_interaction(
-    manifests: &Vec<K8SManifest>,
-    is_to_refactor: bool
-) {
-    let mut microservices_hashmap: HashMap<String, Microservice> = HashMap::new();
+pub fn check_endpoint_based_interaction(ctx: &AnalysisContext) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+    let mut microservices_hashmap: HashMap<Host, Microservice> = HashMap::new();
 
-    let config = yaml_handler::get_config();
+    let config = &ctx.config;
 
-    for invoked_service in &config.invoked_services[..] {
-        if let Some(deployment) = yaml_handler::
-            get_deployment_named(
-                invoked_service.clone(),
-                manifests
-        ) {
+    for (_, invoked_host) in ctx.invoked_services() {
+        if let Some(deployment) = ctx.workload_for(&invoked_host) {
 
             let microservice = Microservice {
                 has_service: false,
                 has_direct_access: yaml_handler::
-                    deployment_has_direct_access(deployment)
+                    deployment_has_direct_access(deployment),
+                external_services: vec![]
             };
 
             microservices_hashmap.insert(
-                invoked_service.clone(),
+                invoked_host,
                 microservice
             );
         }
@@ -85,424 +88,618 @@ pub fn check_endpoint_based_interaction(
 
     // iterate through k8s services and link them
     // to appropriate nodes in node_hashmap
-    let services_manifests = yaml_handler::
-        get_services(manifests);
-    let deployments_manifests = yaml_handler::
-        get_deployments_pods(manifests);
-
-    for service_manifest in &services_manifests {
-        if let Some(selector) = &service_manifest.spec.selector {
-
-            let mut name = "";
-
-            if let Some(first) = selector
-                .values()
-                .next() {
-                name = first
-                    .as_str()
-                    .unwrap();
-            }
+    for service_manifest in ctx.services() {
+        // a service is attached to the workloads whose pods it selects
+        for workload in ctx.selected_by(service_manifest) {
+            let host = Host {
+                name: workload.metadata.name.clone(),
+                namespace: ctx.namespace_of(workload).to_owned(),
+            };
+
+            // set the bool as true so that we can identify tosca services that have
+            // an attached k8s service
+            if let Some(node) = microservices_hashmap.get_mut(&host) {
+                node.has_service = true;
 
-            if !name.is_empty() { 
-                // if exists a service with the selector.app = tosca service name
-                if let Some(node) = microservices_hashmap
-                    .get(name) {
-                    // set the bool as true so that we can identify tosca services that have
-                    // an attached k8s service
-                    let updated_microservice = Microservice {
-                        has_service: true,
-                        has_direct_access: node.has_direct_access
-                    };
-                    microservices_hashmap.insert(
-                        name.to_string(),
-                        updated_microservice
-                    );
+                if service_manifest.is_external_service() {
+                    node.external_services.push(service_manifest.metadata.name.clone());
                 }
             }
         }
     }
 
-    for invoked_service in &config.invoked_services[..] {
-        if config.ignore_smells.endpoint_based_interaction.contains(invoked_service) { continue }
-        if let Some(dest_node) = microservices_hashmap.get(invoked_service) {
+    for (invoked_service, invoked_host) in ctx.invoked_services() {
+        if config.ignore_smells.endpoint_based_interaction.iter().any(|s| s == invoked_service) { continue }
+        if let Some(dest_node) = microservices_hashmap.get(&invoked_host) {
             // We need to assure that the only way to access
-            // B is through k8s services, so we have to check that 
-            // the node.has_service is true and we also have to 
+            // B is through k8s services, so we have to check that
+            // the node.has_service is true and we also have to
             // check that the service named node_name has not in the manifest
             // any hostPort or hostNetwork
             if dest_node.has_direct_access {
                 // possible smell
-                println!(
-                    "{}(*) Service named {} is an invoked service, \n\
-                    but it is reachable directly by using a host port \n\
-                    you declared. {} remove every host network and host port\n",
-                    format!("! [Endpoint Based Interaction]\n").red().bold(),
-                    format!("{}", invoked_service).cyan().bold(),
-                    format!("\nHint:").yellow().italic()
-                );
-
-                if is_to_refactor {
-                    if let Some(mut invoked_service_manifest) 
-                    = deployments_manifests
-                    .clone()
-                    .into_iter()
-                    .find(|man| 
-                        man.metadata.name == *invoked_service
-                    ) {
-                        // * Removing every host network or host port
-
-                        invoked_service_manifest.spec.hostNetwork = None;
-
-                        // pod case
-                        if let Some(containers) 
-                        = &invoked_service_manifest.spec.containers {
-                            let mut pod_refactored_containers: Vec<Container> = containers.clone();
-                            for container in containers { 
-                                let mut c = container.clone();
-                                let has_host_ports = !&container
-                                    .ports.is_none() && container.ports.as_ref()
-                                    .unwrap()
-                                    .into_iter()
-                                    .any(|port|
-                                        !port.hostPort.is_none()
-                                    );
-
-                                if has_host_ports { c.ports = None }
-                                pod_refactored_containers.push(c);
-                            }
-                            invoked_service_manifest.spec.containers = Some(pod_refactored_containers);
-                        }
-
-                        // deployment case
-                        if let Some(template) = &invoked_service_manifest.spec.template {
-                            if let Some(containers) = &template.spec.containers {
-                                let mut depl_refactored_containers: Vec<Container> = Vec::new();
-                                for container in containers {
-                                    let mut c = container.clone();
-                                    let has_host_ports = !&container.ports.is_none() && container.ports.as_ref()
-                                        .unwrap()
-                                        .into_iter()
-                                        .any(|port| !port.hostPort.is_none());
-
-                                    if has_host_ports { c.ports = None }
-                                    // println!("Pushing container: {:#?}", c);
-                                    depl_refactored_containers.push(c);
-                                }
-                                let mut temp = template.clone();
-                                temp.spec.containers = Some(depl_refactored_containers);
-                                invoked_service_manifest.spec.template = Some(temp);
-                            }
+                let mut finding = Finding::new(
+                    Smell::EndpointBasedInteraction,
+                    Severity::Error,
+                    format!(
+                        "Service named {} is an invoked service, \
+                        but it is reachable directly by using a host port you declared.",
+                        invoked_service
+                    )
+                )
+                .with_service(invoked_service)
+                .with_hint("remove every host network and host port.");
+
+                if let Some(deployment) = ctx.workload_for(&invoked_host) {
+                    // * Removing every host network or host port
+                    finding = finding
+                        .on_manifest(deployment)
+                        .with_fix(Fix::RemoveHostNetwork);
+
+                    for container in deployment.all_containers() {
+                        let has_host_ports = container.ports
+                            .as_ref()
+                            .is_some_and(|ports| ports.iter().any(|port| port.hostPort.is_some()));
+
+                        if has_host_ports {
+                            finding = finding.with_fix(
+                                Fix::RemoveHostPorts { container: container.name.clone() }
+                            );
                         }
-                    
-                        let filename = format!("{}{}", invoked_service_manifest.metadata.name, ".yaml");
-                        
-                        yaml_handler::update_manifest(&invoked_service_manifest, filename);
-                        
                     }
                 }
-                
+
+                findings.push(finding);
+            }
+
+            if !dest_node.external_services.is_empty() {
+                // possible smell, as NodePort and LoadBalancer services make
+                // the pods reachable from outside of the cluster on each node
+                let mut finding = Finding::new(
+                    Smell::EndpointBasedInteraction,
+                    Severity::Error,
+                    format!(
+                        "Service named {} is an invoked service, \
+                        but it is reachable directly from outside the cluster through {}.",
+                        invoked_service,
+                        dest_node.external_services.join(", ")
+                    )
+                )
+                .with_service(invoked_service)
+                .with_hint("expose it through an API gateway and turn its NodePort or LoadBalancer services into ClusterIP ones.");
+
+                if let Some(deployment) = ctx.workload_for(&invoked_host) {
+                    finding = finding.on_manifest(deployment);
+                }
+
+                findings.push(finding);
             }
 
             if !dest_node.has_service {
                 // possible smell
-                println!(
-                    "{}(*) Service named {} is reached by another microservice,\n\
-                    but there's no k8s service associated with it.\n\
-                    {} remove every host network and host port and use a k8s \n\
-                    service instead.\n",
-                    format!("! [Endpoint Based Interaction]\n").red().bold(),
-                    format!("{}", invoked_service).cyan().bold(),
-                    format!("\nHint:").yellow().italic(),
-                );
+                let mut finding = Finding::new(
+                    Smell::EndpointBasedInteraction,
+                    Severity::Error,
+                    format!(
+                        "Service named {} is reached by another microservice, \
+                        but there's no k8s service associated with it.",
+                        invoked_service
+                    )
+                )
+                .with_service(invoked_service)
+                .with_hint("remove every host network and host port and use a k8s service instead.");
+
+                // the new service selects the pods of the workload by their labels
+                let workload = ctx.workload_for(&invoked_host);
+                let mut selector = workload.map(|w| w.pod_labels()).unwrap_or_default();
+                if selector.is_empty() {
+                    selector.0.insert(String::from("app"), invoked_host.name.clone());
+                }
+
+                finding = finding.with_fix(Fix::CreateService {
+                    name: invoked_host.name.clone(),
+                    namespace: ctx.namespace_for(&invoked_host),
+                    selector,
+                });
 
-                if is_to_refactor {
-                    yaml_handler::create_service_from(invoked_service.to_string());
+                if let Some(deployment) = workload {
+                    finding = finding.on_manifest(deployment);
                 }
+
+                findings.push(finding);
             }
         }
     }
+
+    findings
 }
 
-pub fn check_no_apigateway(manifests: &Vec<K8SManifest>, is_to_refactor: bool) {
-    let deployment_manifest = yaml_handler::get_deployments_pods(manifests);
+pub fn check_no_apigateway(ctx: &AnalysisContext) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
 
-    for mut manifest in deployment_manifest {
-        /* 
+    for manifest in ctx.workloads() {
+        /*
         if hostNetwork is set as true or inside a container there's ports.-hostPort,
         and there's no image that represent an official Docker image that implements
         message routing components then a horizontal scalability violation can occur
         */
-        let containers = &manifest.spec.containers;
-        
-        let host_network: bool = if let Some(hn) = &manifest.spec.hostNetwork { *hn } else { false };
-
-        if is_to_refactor && host_network {
-            manifest.spec.hostNetwork = None;
-            let filename = format!("{}{}", manifest.metadata.name, ".yaml");
-            // println!("{} has been modified with\n{:#?}", filename, man);
-            yaml_handler::update_manifest(&manifest, filename); 
-        }
+        if let Some(pod) = manifest.pod_spec() {
+            findings.extend(analyze_containers_nag(ctx, manifest, pod.containers, pod.host_network));
 
-        // if manifest represents a pod
-        if let Some(conts) = containers {
-            let result = analyze_containers_nag(&manifest, &conts, host_network);
-
-            if result.1 && is_to_refactor {
-                let filename = format!("{}{}", manifest.metadata.name, ".yaml");
-                let mut manifest_cpy = manifest.clone();
-                manifest_cpy.spec.containers = Some(result.0);
-                
-                if result.2 {
-                    manifest_cpy.spec.hostNetwork = None;
+            // the same holds for the pods exposed by NodePort or LoadBalancer services
+            for service in ctx.services_selecting(manifest) {
+                if service.is_external_service() {
+                    findings.extend(analyze_exposed_containers_nag(ctx, manifest, pod.containers, service));
                 }
-                
-                // println!("{} has been modified with\n{:#?}", filename, man);
-                yaml_handler::update_manifest(&manifest_cpy, filename); 
             }
+        }
+    }
 
-            return
+    findings
+}
+
+pub fn check_unmatched_target_port(ctx: &AnalysisContext) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+
+    for service in ctx.services() {
+        if ctx.config.ignore_smells.unmatched_target_port.contains(&service.metadata.name) { continue }
+
+        let workloads = ctx.selected_by(service);
+        let ports: Vec<&Port> = workloads
+            .iter()
+            .flat_map(|w| w.all_containers())
+            .flat_map(|c| c.ports.iter().flatten())
+            .collect();
+
+        for target in service.spec.ports.iter().flatten().filter_map(|p| p.target()) {
+            // containers may listen on ports they don't declare, hence numeric
+            // targets are checked only against pods declaring their ports
+            let is_checkable = match target {
+                PortRef::Number(_) => !ports.is_empty(),
+                PortRef::Name(_) => !workloads.is_empty(),
+            };
+
+            if !is_checkable || ports.iter().any(|p| p.is_target_of(&target)) { continue }
+
+            let names: Vec<&str> = workloads.iter().map(|w| w.metadata.name.as_str()).collect();
+            findings.push(
+                Finding::new(
+                    Smell::UnmatchedTargetPort,
+                    Severity::Error,
+                    format!(
+                        "Service named {} forwards traffic to the target port {}, \
+                        but no container of the pods it selects ({}) declares it.",
+                        service.metadata.name,
+                        target,
+                        names.join(", ")
+                    )
+                )
+                .on_manifest(service)
+                .with_service(&service.metadata.name)
+                .with_hint("set the targetPort to a containerPort (or to the name of a port) of the selected pods.")
+            );
         }
-        
-        // if manifest represents a deployment
-        if let Some(template) = &manifest.spec.template {
-            if let Some(nested_containers) = &template.spec.containers {
-                let result = analyze_containers_nag(&manifest, &nested_containers, host_network);
-
-                if result.1 && is_to_refactor {
-                    let filename = format!("{}-Deployment{}", manifest.metadata.name, ".yaml");
-                    let mut manifest_cpy = manifest.clone();
-
-                    if result.2 {
-                        manifest_cpy.spec.hostNetwork = Some(false);
-                    }
-                    
-                    if let Some(template) = manifest.spec.template {
-                        let _templae_spec = TemplateSpec {
-                            initContainers: template.spec.initContainers,
-                            containers: Some(result.0),
-                            volumes: template.spec.volumes
-                        };
-
-                        let _template = Template { spec: _templae_spec, metadata: template.metadata };
-                        manifest_cpy.spec.template = Some(_template);
-                        manifest_cpy.spec.containers = None;
-                    }
+    }
 
-                    yaml_handler::update_manifest(&manifest_cpy, filename); 
-                }
+    findings
+}
+
+pub fn check_shared_persistence(ctx: &AnalysisContext) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+    let ignored = &ctx.config.ignore_smells.shared_persistence;
+
+    // the workloads mounting each persistent volume claim of each namespace
+    let mut claims: BTreeMap<(&str, &str), Vec<&K8SManifest>> = BTreeMap::new();
+    for workload in ctx.workloads() {
+        for claim in workload.claimed_volumes() {
+            let consumers = claims.entry((ctx.namespace_of(workload), claim)).or_default();
+            if !consumers.iter().any(|w| std::ptr::eq(*w, workload)) {
+                consumers.push(workload);
             }
         }
     }
+
+    for ((namespace, claim), consumers) in claims {
+        if consumers.len() < 2 || ignored.iter().any(|s| s == claim) { continue }
+
+        let names: Vec<&str> = consumers.iter().map(|w| w.metadata.name.as_str()).collect();
+        let declared = ctx.named(claim)
+            .into_iter()
+            .find(|m| m.kind == "PersistentVolumeClaim" && ctx.namespace_of(m) == namespace);
+
+        let mut finding = Finding::new(
+            Smell::SharedPersistence,
+            Severity::Error,
+            format!(
+                "PersistentVolumeClaim named {} is mounted by multiple workloads ({}).",
+                claim,
+                names.join(", ")
+            )
+        )
+        .on_manifest(declared.unwrap_or(consumers[0]))
+        .with_hint("give each workload a volume (or a datastore) of its own, and let the others reach its data through its API.");
+
+        // each consumer gets a claim like the shared one, in the same namespace
+        let host = Host { name: claim.to_owned(), namespace: namespace.to_owned() };
+        for consumer in consumers {
+            finding = finding.with_fix(Fix::CreateVolumeClaim {
+                name: format!("{}-{}", consumer.metadata.name, claim),
+                namespace: ctx.namespace_for(&host),
+                claim: claim.to_owned(),
+                consumer: ManifestRef::of(consumer),
+            });
+        }
+
+        findings.push(finding);
+    }
+
+    let interactions = inference::interactions(ctx);
+    let datastores = ctx.workloads()
+        .into_iter()
+        .filter(|w| graph::kind_of_workload(w) == NodeKind::Database);
+
+    for datastore in datastores {
+        let name = &datastore.metadata.name;
+        if ignored.contains(name) { continue }
+
+        // the datastores replicating each other are not consumers of theirs
+        let host = Host { name: name.clone(), namespace: ctx.namespace_of(datastore).to_owned() };
+        let consumers: Vec<&K8SManifest> = interactions
+            .iter()
+            .filter(|i| i.callee == host)
+            .filter_map(|i| i.caller.as_ref().and_then(|c| ctx.workload_for(c)))
+            .filter(|w| graph::kind_of_workload(w) != NodeKind::Database)
+            .collect();
+
+        if consumers.len() < 2 { continue }
+
+        let names: Vec<&str> = consumers.iter().map(|w| w.metadata.name.as_str()).collect();
+        let mut finding = Finding::new(
+            Smell::SharedPersistence,
+            Severity::Error,
+            format!(
+                "Datastore named {} is accessed by multiple microservices ({}).",
+                name,
+                names.join(", ")
+            )
+        )
+        .on_manifest(datastore)
+        .with_service(name)
+        .with_hint("give each microservice a datastore of its own, and let the others reach its data through its API.");
+
+        // each consumer gets a datastore running the same database, in its own namespace
+        let database = datastore.all_containers()
+            .into_iter()
+            .find(|c| c.image.as_deref().is_some_and(graph::is_database_image));
+
+        if let Some(database) = database {
+            let port = database.ports.iter().flatten().find_map(|p| p.container_port);
+
+            for consumer in consumers {
+                let consumer_host = Host {
+                    name: consumer.metadata.name.clone(),
+                    namespace: ctx.namespace_of(consumer).to_owned(),
+                };
+
+                finding = finding.with_fix(Fix::CreateDatastore {
+                    name: format!("{}-{}", consumer.metadata.name, name),
+                    namespace: ctx.namespace_for(&consumer_host),
+                    image: database.image.clone().unwrap_or_default(),
+                    port,
+                    consumer: ManifestRef::of(consumer),
+                });
+            }
+        }
+
+        findings.push(finding);
+    }
+
+    findings
 }
 
-pub fn check_independent_depl(manifests: &Vec<K8SManifest>, is_to_refactor: bool) {
-    let deployment_manifests = yaml_handler::get_deployments_pods(manifests);
+pub fn check_hardcoded_endpoints(ctx: &AnalysisContext) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+    let ignored = &ctx.config.ignore_smells.hardcoded_endpoint;
+    let mut config_maps: Vec<&K8SManifest> = vec![];
 
-    for manifest in deployment_manifests {
+    for workload in ctx.workloads() {
+        let namespace = ctx.namespace_of(workload);
 
-        let mut manifest_cpy = manifest.clone();
-        let filename = format!("{}{}", manifest.metadata.name, ".yaml");
-        
+        for config_map in inference::consumed_config_maps(ctx, workload) {
+            if !config_maps.iter().any(|c| std::ptr::eq(*c, config_map)) {
+                config_maps.push(config_map);
+            }
+        }
 
-        // checking independent deployability
-        if manifest.kind == "Pod" {
-            let containers = &manifest.spec.containers;
-            if let Some(mut containers) = containers.clone() {
-                let refactored_containers = analyze_multiple_containers(
-                    &mut containers, 
-                    manifest.metadata.name.clone(),
-                     is_to_refactor
-                );
-                manifest_cpy.spec.containers = Some(refactored_containers);
-             }
-        } else if manifest.kind == "Deployment" {
-            if let Some(template) = manifest.spec.template {
-                if let Some(mut nested_containers) = template.spec.containers {
-                    let refactored_containers = analyze_multiple_containers(
-                        &mut nested_containers,
-                         manifest.metadata.name.clone(),
-                          is_to_refactor
-                    );
-                    
-                    let _spec = TemplateSpec {
-                        initContainers: template.spec.initContainers,
-                        containers: Some(refactored_containers),
-                        volumes: template.spec.volumes
-                    };
-                    let _template = Template {
-                        spec: _spec,
-                        metadata: template.metadata
-                    };
-
-                    manifest_cpy.spec.template = Some(_template);
-                    manifest_cpy.spec.containers = None;
+        if ignored.contains(&workload.metadata.name) { continue }
+
+        for container in workload.all_containers() {
+            for env in container.env.iter().flatten() {
+                let Some(value) = &env.value else { continue };
+
+                let endpoints = inference::hardcoded_endpoints(ctx, &env.name, value, namespace);
+                if endpoints.is_empty() { continue }
+
+                let mut finding = hardcoded_endpoint_finding(
+                    format!("Env var {} of container {}", env.name, container.name),
+                    &endpoints
+                )
+                .on_manifest(workload)
+                .with_container(&container.name);
+
+                if let Some(value) = inference::through_services(value, &endpoints) {
+                    finding = finding.with_fix(Fix::ReplaceEnvValue {
+                        container: container.name.clone(),
+                        name: env.name.clone(),
+                        value,
+                    });
                 }
+
+                findings.push(finding);
             }
         }
+    }
+
+    for config_map in config_maps {
+        if ignored.contains(&config_map.metadata.name) { continue }
+
+        for (key, value) in inference::data_entries(config_map) {
+            let endpoints = inference::hardcoded_endpoints(ctx, &key, &value, ctx.namespace_of(config_map));
+            if endpoints.is_empty() { continue }
+
+            let mut finding = hardcoded_endpoint_finding(
+                format!("Entry {} of ConfigMap {}", key, config_map.metadata.name),
+                &endpoints
+            )
+            .on_manifest(config_map);
 
-        // manifest_cpy is the refactored manifest
-        // now if solve flag is set as true we can
-        // override the "manifest.metadata.name".yaml
-        // with the refactored version
-        if is_to_refactor {
-            yaml_handler::update_manifest(&manifest_cpy, filename);
+            if let Some(value) = inference::through_services(&value, &endpoints) {
+                finding = finding.with_fix(Fix::ReplaceConfigValue { key, value });
+            }
+
+            findings.push(finding);
         }
+    }
 
+    findings
+}
+
+/// it returns a finding for the endpoints hardcoded in a value, which tells
+/// the k8s services to reach them through (when known)
+fn hardcoded_endpoint_finding(holder: String, endpoints: &[inference::HardcodedEndpoint]) -> Finding {
+    let described: Vec<String> = endpoints
+        .iter()
+        .map(|e| match &e.service {
+            Some(service) => format!("{} (reachable as {})", e.host, service),
+            None => e.host.clone(),
+        })
+        .collect();
+
+    Finding::new(
+        Smell::HardcodedEndpoint,
+        Severity::Error,
+        format!(
+            "{} hardcodes {}, instead of going through a k8s service.",
+            holder,
+            described.join(", ")
+        )
+    )
+    .with_hint("replace the IP addresses and the names of pods and workloads with the DNS names of the k8s services reaching them.")
+}
+
+pub fn check_independent_depl(ctx: &AnalysisContext) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
+
+    for manifest in ctx.workloads() {
+        // checking independent deployability
+        if let Some(pod) = manifest.pod_spec() {
+            findings.extend(analyze_multiple_containers(ctx, manifest, pod.containers));
+        }
     }
+
+    findings
 }
 
-/// it returns the refactored vector of containers
-fn analyze_multiple_containers(containers: &Vec<Container>, metadata_name: String, is_to_refactor: bool) -> Vec<Container> {
+/// it returns a finding for each container that may not be a sidecar
+fn analyze_multiple_containers(
+    ctx: &AnalysisContext,
+    manifest: &K8SManifest,
+    containers: &[Container]
+) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
     let mut main_container_name = String::new();
-    let mut result_containers: Vec<Container> = containers.clone();
-
-    // check config file
-    let config = yaml_handler::get_config();
 
     for container in containers {
-        let node_config_element = config.ignore_smells
+        let node_config_element = ctx.config.ignore_smells
             .multiple_container
             .iter()
             .find(
-                |c| c.name == metadata_name
+                |c| c.name == manifest.metadata.name
             );
 
         let has_pattern = get_patterns().iter()
             .any(|pattern| -> bool {
-                container.name.contains(pattern) 
-                || container.image.contains(pattern)
+                container.name.contains(pattern)
+                || container.image.as_ref().is_some_and(|image| image.contains(pattern))
             });
-    
+
         let mut has_known_sidecar: bool = false;
-        
+
         if let Some(node_element) = node_config_element {
-            if node_element.containers.is_none() { 
-                has_known_sidecar = false 
-            } else {
-                has_known_sidecar = node_element.containers
-                .as_ref()
-                .unwrap()
-                .iter()
-                .any(|c| *c == container.name);
-            }
+            has_known_sidecar = match &node_element.containers {
+                Some(sidecars) => sidecars.contains(&container.name),
+                None => false
+            };
         }
-                
-        if !(has_pattern || has_known_sidecar) {
+
+        let is_registered_sidecar = container.image
+            .as_ref()
+            .is_some_and(|image| ctx.is_known_image(image, ImageKind::Sidecar));
+
+        if !(has_pattern || has_known_sidecar || is_registered_sidecar) {
             if !main_container_name.is_empty() {
-                println!(
-                    "{}{}\n(*) Container named {} may not be a sidecar, \n\
-                    we cannot assure {} is a proper sidecar.\n",
-                    format!("! [Multiple containers per Deployment] => ").red().bold(),
-                    format!("in {}", metadata_name).yellow().bold(),
-                    format!("{}", container.name).cyan().bold(),
-                    format!("{}", container.image).bright_purple().bold(),
+                // solving by moving the "wrong" container to a new pod
+                // named as the container and with the same image
+                findings.push(
+                    Finding::new(
+                        Smell::MultipleContainer,
+                        Severity::Warning,
+                        format!(
+                            "Container named {} may not be a sidecar, \
+                            we cannot assure {} is a proper sidecar.",
+                            container.name,
+                            container.image.as_deref().unwrap_or("its image")
+                        )
+                    )
+                    .on_manifest(manifest)
+                    .with_container(&container.name)
+                    .with_fix(Fix::ExtractContainer { container: Box::new(container.clone()) })
                 );
 
-                // solving by creating a new pod named as the "wrong" container name
-                // and with the same image
-                if is_to_refactor {
-                    yaml_handler::create_pod_from(container);
-                }
-
-                // then remove the "wrong" container from the current pod/deployment
-                result_containers = result_containers
-                    .into_iter()
-                    .filter(|c| c.name != container.name)
-                    .collect();
-            
                 continue;
-            } 
+            }
             main_container_name = container.name.clone();
         }
     }
 
-    result_containers
+    findings
 }
 
-fn analyze_containers_nag(manifest: &K8SManifest, containers: &Vec<Container>, host_network: bool) -> (Vec<Container>, bool, bool) {
-    let mut result_containers: Vec<Container> = Vec::new();
-    let mut has_to_update = false;
-    let mut remove_host_network = false;
+fn analyze_containers_nag(
+    ctx: &AnalysisContext,
+    manifest: &K8SManifest,
+    containers: &[Container],
+    host_network: bool
+) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = vec![];
 
     for container in containers {
-        let mut c = container.clone();
+        // what a container runs cannot be told until its image is set
+        let Some(image) = &container.image else { continue };
+
         if host_network && !implements_message_routing(
+            ctx,
             manifest.metadata.name.clone(),
-            container.image.clone()
+            image.clone()
         ) {
-            println!(
-                "{}{}\n(*) HostNetwork is set to true and container's (named '{}'), \n\
-                image '{}' may not implement message routing.\n",
-                format!("! [No API Gateway] => ").red().bold(),
-                format!("in {}", &manifest.metadata.name).yellow().bold(),
-                format!("{}", container.name).cyan().bold(), 
-                format!("{}", container.image).bright_purple().bold(),
+            findings.push(
+                Finding::new(
+                    Smell::NoApiGateway,
+                    Severity::Warning,
+                    format!(
+                        "HostNetwork is set to true and container's (named '{}') \
+                        image '{}' may not implement message routing.",
+                        container.name,
+                        image
+                    )
+                )
+                .on_manifest(manifest)
+                .with_container(&container.name)
+                .with_fix(Fix::RemoveHostNetwork)
             );
-
-            has_to_update = true;
-            remove_host_network = true;
         }
 
         if let Some(ports) = &container.ports {
             // check if the current container has at least one host port
             let has_host_port = ports
-                .into_iter()
-                .any(|port| !port.hostPort.is_none());
+                .iter()
+                .any(|port| port.hostPort.is_some());
 
             // if it's true, then we have to verify that the current container is running
             // an official Docker image that implements message routing
             if has_host_port && !implements_message_routing(
+                ctx,
                 manifest.metadata.name.clone(),
-                container.image.clone()
+                image.clone()
             ) {
-                println!(
-                    "{}{}\n(*) Container named '{}' has an hostPort associated, \n\
-                    and its image '{}' may not implement message routing.\n",
-                    format!("! [No API Gateway] => ").red().bold(),
-                    format!("in {}", &manifest.metadata.name).yellow().bold(),
-                    format!("{}", container.name).cyan().bold(),
-                    format!("{}", container.image).bright_purple().bold(),
+                findings.push(
+                    Finding::new(
+                        Smell::NoApiGateway,
+                        Severity::Warning,
+                        format!(
+                            "Container named '{}' has an hostPort associated, \
+                            and its image '{}' may not implement message routing.",
+                            container.name,
+                            image
+                        )
+                    )
+                    .on_manifest(manifest)
+                    .with_container(&container.name)
+                    .with_fix(Fix::RemoveHostPorts { container: container.name.clone() })
                 );
-
-                c.ports = None;
-
-                has_to_update = true;
             }
         }
-
-        result_containers.push(c);
     }
 
-    (result_containers, has_to_update, remove_host_network)
+    findings
+}
 
+/// It returns a finding for each container exposed by a NodePort or
+/// LoadBalancer service that may not implement message routing, except
+/// for sidecars (e.g., proxies), which are not meant to route messages
+fn analyze_exposed_containers_nag(
+    ctx: &AnalysisContext,
+    manifest: &K8SManifest,
+    containers: &[Container],
+    service: &K8SManifest
+) -> Vec<Finding> {
+    let is_sidecar = |container: &Container, image: &str| {
+        ctx.is_known_image(image, ImageKind::Sidecar)
+            || get_patterns().iter().any(|p| container.name.contains(p) || image.contains(p))
+    };
+
+    // what a container runs cannot be told until its image is set
+    containers
+        .iter()
+        .filter_map(|c| c.image.as_ref().map(|image| (c, image)))
+        .filter(|(c, image)| !is_sidecar(c, image))
+        .filter(|(_, image)| !implements_message_routing(ctx, manifest.metadata.name.clone(), image.to_string()))
+        .map(|(container, image)| {
+            Finding::new(
+                Smell::NoApiGateway,
+                Severity::Warning,
+                format!(
+                    "Container named '{}' is exposed outside the cluster by the {} service '{}', \
+                    and its image '{}' may not implement message routing.",
+                    container.name,
+                    service.spec.service_type.as_deref().unwrap_or_default(),
+                    service.metadata.name,
+                    image
+                )
+            )
+            .on_manifest(manifest)
+            .with_container(&container.name)
+        })
+        .collect()
 }
 
 fn implements_message_routing(
+    ctx: &AnalysisContext,
     pod_name: String,
     image_name: String
 ) -> bool {
-    if let Some(node_config_element) = yaml_handler
-        ::get_config()
+    if ctx.is_known_image(&image_name, ImageKind::Mr) {
+        return true
+    }
+
+    if let Some(node_config_element) = ctx.config
         .ignore_smells
         .noapigateway
         .iter()
         .find(|c| c.name == pod_name) {
-            //ritorna vero nei casi in cui nel 
+            //ritorna vero nei casi in cui nel
             // config si ha - name: catalogue
-            // senza che vengano specificati 
-            // container; questo significa che 
+            // senza che vengano specificati
+            // container; questo significa che
             // dobbiamo ignorare tutti i container
             // dentro catalogue
-            if node_config_element.containers.is_none() {
-                return true 
+            return match &node_config_element.containers {
+                Some(containers) => containers
+                    .iter()
+                    .any(|c| image_name.contains(c.as_str())),
+                None => true
             }
-            return node_config_element
-                .containers
-                .as_ref()
-                .unwrap()
-                .iter()
-                .any(|c| image_name.contains(&*c)) 
     }
 
     false
-}
\ No newline at end of file
+}
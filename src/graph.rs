@@ -0,0 +1,351 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::context::AnalysisContext;
+use crate::inference;
+use crate::k8s_types::*;
+use crate::report::{ManifestRef, Report, Smell};
+
+/// The images of well-known databases, as named by their repository (i.e., the last
+/// segment of its path), including their official variants but not their tools
+/// (e.g., `redis-exporter` or `mongo-express`)
+const DATABASE_IMAGES: [&str; 18] = [
+    "mysql", "mysql-server", "mariadb", "postgres", "postgresql", "postgis", "mongo", "mongodb",
+    "redis", "redis-stack", "redis-stack-server", "cassandra", "couchdb", "elasticsearch",
+    "memcached", "neo4j", "cockroach", "influxdb",
+];
+
+/// The images of well-known message brokers, as named by their repository
+const BROKER_IMAGES: [&str; 15] = [
+    "rabbitmq", "kafka", "cp-kafka", "cp-server", "nats", "nats-streaming", "activemq",
+    "activemq-artemis", "artemis", "pulsar", "pulsar-all", "mosquitto", "eclipse-mosquitto",
+    "emqx", "redpanda",
+];
+
+/// The formats a topology graph can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    /// a workload running (part of) a microservice
+    Workload,
+    /// a workload running a database
+    Database,
+    /// a workload running a message broker
+    MessageBroker,
+    /// a k8s service
+    Service,
+    /// an ingress point of the deployment
+    Ingress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    /// a microservice calls another one
+    Invokes,
+    /// a k8s service selects the pods of a workload
+    Selects,
+    /// an ingress routes the traffic to a k8s service
+    Routes,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub kind: NodeKind,
+    /// the kind of the k8s object the node stands for
+    pub object: String,
+    pub name: String,
+    pub namespace: String,
+    pub smells: Vec<Smell>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+    pub smells: Vec<Smell>,
+}
+
+/// The topology of a deployment, i.e., its workloads, services and ingress
+/// points together with their interactions, annotated with the smells found
+#[derive(Debug, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// It builds the topology graph of the deployment analyzed in `ctx`,
+    /// annotating its nodes and edges with the smells in `report`
+    pub fn build(ctx: &AnalysisContext, report: &Report) -> Self {
+        let mut graph = Graph::default();
+
+        for workload in ctx.workloads() {
+            graph.add_node(ctx, workload, kind_of_workload(workload));
+        }
+
+        for service in ctx.services() {
+            let id = graph.add_node(ctx, service, NodeKind::Service);
+
+            for workload in ctx.selected_by(service) {
+                graph.add_edge(id.clone(), node_id(ctx, workload), EdgeKind::Selects);
+            }
+        }
+
+        for ingress in ctx.of_kind(&["Ingress"]) {
+            let id = graph.add_node(ctx, ingress, NodeKind::Ingress);
+
+            for backend in inference::ingress_backends(&ingress.raw) {
+                let host = ctx.host_from(&backend, ingress);
+                let service = ctx.named(&host.name)
+                    .into_iter()
+                    .find(|m| m.kind == "Service" && ctx.namespace_of(m) == host.namespace);
+
+                if let Some(service) = service {
+                    graph.add_edge(id.clone(), node_id(ctx, service), EdgeKind::Routes);
+                }
+            }
+        }
+
+        // the callers outside of the workloads are already given by ingresses
+        for interaction in inference::interactions(ctx) {
+            let Some(caller) = interaction.caller.and_then(|c| ctx.workload_for(&c)) else { continue };
+            let Some(callee) = ctx.workload_for(&interaction.callee) else { continue };
+
+            graph.add_edge(node_id(ctx, caller), node_id(ctx, callee), EdgeKind::Invokes);
+        }
+
+        for finding in &report.findings {
+            let Some(manifest) = &finding.manifest else { continue };
+            graph.annotate(ctx, manifest, finding.smell);
+        }
+
+        graph
+    }
+
+    fn add_node(&mut self, ctx: &AnalysisContext, manifest: &K8SManifest, kind: NodeKind) -> String {
+        let id = node_id(ctx, manifest);
+
+        self.nodes.push(Node {
+            id: id.clone(),
+            kind,
+            object: manifest.kind.clone(),
+            name: manifest.metadata.name.clone(),
+            namespace: ctx.namespace_of(manifest).to_owned(),
+            smells: vec![],
+        });
+
+        id
+    }
+
+    fn add_edge(&mut self, from: String, to: String, kind: EdgeKind) {
+        let exists = self.edges.iter().any(|e| e.from == from && e.to == to && e.kind == kind);
+        let has_ends = [&from, &to].iter().all(|id| self.nodes.iter().any(|n| n.id == **id));
+
+        if !exists && has_ends {
+            self.edges.push(Edge { from, to, kind, smells: vec![] });
+        }
+    }
+
+    /// It annotates the node of a manifest with a smell, as well as the edges
+    /// the smell is about: the interactions reaching the node for the smells on
+    /// interactions (and on the datastores they share), and the pods selected
+    /// by the node for its target ports
+    fn annotate(&mut self, ctx: &AnalysisContext, manifest: &ManifestRef, smell: Smell) {
+        let namespace = manifest.namespace
+            .as_deref()
+            .unwrap_or(ctx.config.default_namespace());
+        let id = format!("{}/{}/{}", namespace, manifest.kind, manifest.name);
+
+        if let Some(node) = self.nodes.iter_mut().find(|n| n.id == id) {
+            if !node.smells.contains(&smell) {
+                node.smells.push(smell);
+            }
+        }
+
+        let is_about = |edge: &Edge| match smell {
+            Smell::WobblyInteraction | Smell::EndpointBasedInteraction | Smell::SharedPersistence =>
+                edge.kind == EdgeKind::Invokes && edge.to == id,
+            Smell::UnmatchedTargetPort =>
+                edge.kind == EdgeKind::Selects && edge.from == id,
+            Smell::MultipleContainer | Smell::NoApiGateway | Smell::HardcodedEndpoint => false,
+        };
+
+        for edge in self.edges.iter_mut().filter(|e| is_about(e)) {
+            if !edge.smells.contains(&smell) {
+                edge.smells.push(smell);
+            }
+        }
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+            GraphFormat::Json => serde_json::to_string_pretty(self).unwrap_or_default() + "\n",
+        }
+    }
+
+    /// It renders the graph in the DOT language of Graphviz, with the smelly
+    /// nodes and edges in red (k8s names need no escaping, being DNS names)
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph deployment {\n  rankdir=LR;\n  node [fontname=\"Helvetica\"];\n\n");
+
+        for node in &self.nodes {
+            let shape = match node.kind {
+                NodeKind::Workload => "box",
+                NodeKind::Database => "cylinder",
+                NodeKind::MessageBroker => "hexagon",
+                NodeKind::Service => "ellipse",
+                NodeKind::Ingress => "invhouse",
+            };
+
+            dot.push_str(&format!(
+                "  \"{}\" [label=\"{}\", shape={}{}];\n",
+                node.id,
+                label_of(node, "\\n"),
+                shape,
+                if node.smells.is_empty() { "" } else { ", color=red, fontcolor=red" },
+            ));
+        }
+
+        dot.push('\n');
+
+        for edge in &self.edges {
+            let mut label = edge_label(edge.kind).to_owned();
+            if !edge.smells.is_empty() {
+                label.push_str(&format!("\\n{}", smell_ids(&edge.smells)));
+            }
+
+            dot.push_str(&format!(
+                "  \"{}\" -> \"{}\" [label=\"{}\"{}];\n",
+                edge.from,
+                edge.to,
+                label,
+                if edge.smells.is_empty() { "" } else { ", color=red, fontcolor=red" },
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// It renders the graph as a Mermaid flowchart, with the smelly nodes
+    /// and edges in red
+    fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("flowchart LR\n");
+        // node ids are made of the characters that Mermaid accepts
+        let mermaid_id = |id: &str| format!("n{}", self.nodes.iter().position(|n| n.id == id).unwrap_or_default());
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = label_of(node, "<br/>").replace('"', "#quot;");
+
+            let shape = match node.kind {
+                NodeKind::Workload => format!("[\"{}\"]", label),
+                NodeKind::Database => format!("[(\"{}\")]", label),
+                NodeKind::MessageBroker => format!("{{{{\"{}\"}}}}", label),
+                NodeKind::Service => format!("([\"{}\"])", label),
+                NodeKind::Ingress => format!(">\"{}\"]", label),
+            };
+
+            mermaid.push_str(&format!("  n{}{}\n", i, shape));
+        }
+
+        for edge in &self.edges {
+            let mut label = edge_label(edge.kind).to_owned();
+            if !edge.smells.is_empty() {
+                label.push_str(&format!("<br/>{}", smell_ids(&edge.smells)));
+            }
+
+            mermaid.push_str(&format!(
+                "  {} -->|\"{}\"| {}\n",
+                mermaid_id(&edge.from),
+                label,
+                mermaid_id(&edge.to)
+            ));
+        }
+
+        let smelly_nodes: Vec<String> = self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| !n.smells.is_empty())
+            .map(|(i, _)| format!("n{}", i))
+            .collect();
+
+        if !smelly_nodes.is_empty() {
+            mermaid.push_str("  classDef smelly stroke:#d00,stroke-width:2px,color:#d00\n");
+            mermaid.push_str(&format!("  class {} smelly\n", smelly_nodes.join(",")));
+        }
+
+        for (i, _) in self.edges.iter().enumerate().filter(|(_, e)| !e.smells.is_empty()) {
+            mermaid.push_str(&format!("  linkStyle {} stroke:#d00,color:#d00\n", i));
+        }
+
+        mermaid
+    }
+}
+
+/// It tells whether a workload runs a microservice, a database or a message broker
+pub fn kind_of_workload(workload: &K8SManifest) -> NodeKind {
+    let containers = workload.all_containers();
+
+    let images: Vec<&str> = containers.iter().filter_map(|c| c.image.as_deref()).collect();
+
+    if images.iter().any(|image| is_database_image(image)) {
+        NodeKind::Database
+    } else if images.iter().any(|image| runs_any(image, &BROKER_IMAGES)) {
+        NodeKind::MessageBroker
+    } else {
+        NodeKind::Workload
+    }
+}
+
+/// It tells whether an image is the one of a well-known database
+pub fn is_database_image(image: &str) -> bool {
+    runs_any(image, &DATABASE_IMAGES)
+}
+
+/// It tells whether the repository of an image is named as one of `images`,
+/// whatever its registry, its organization and its tag (or digest)
+fn runs_any(image: &str, images: &[&str]) -> bool {
+    let repository = image.split('@').next().unwrap_or_default();
+    let repository = repository.rsplit('/').next().unwrap_or_default();
+    let repository = repository.split(':').next().unwrap_or_default().to_lowercase();
+
+    images.iter().any(|i| repository == *i)
+}
+
+fn node_id(ctx: &AnalysisContext, manifest: &K8SManifest) -> String {
+    format!("{}/{}/{}", ctx.namespace_of(manifest), manifest.kind, manifest.metadata.name)
+}
+
+fn label_of(node: &Node, separator: &str) -> String {
+    let mut label = format!("{}{}{}", node.name, separator, node.object);
+
+    if !node.smells.is_empty() {
+        label.push_str(&format!("{}{}", separator, smell_ids(&node.smells)));
+    }
+
+    label
+}
+
+fn edge_label(kind: EdgeKind) -> &'static str {
+    match kind {
+        EdgeKind::Invokes => "invokes",
+        EdgeKind::Selects => "selects",
+        EdgeKind::Routes => "routes",
+    }
+}
+
+fn smell_ids(smells: &[Smell]) -> String {
+    smells.iter().map(|s| s.id()).collect::<Vec<&str>>().join(", ")
+}
@@ -0,0 +1,478 @@
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+use serde_yaml::Value;
+
+use crate::context::AnalysisContext;
+use crate::k8s_types::*;
+
+/// The suffixes of the names of the env vars (and of the config entries)
+/// conventionally holding the address of the service a client calls
+const ADDRESS_SUFFIXES: [&str; 6] = ["_HOST", "_HOSTNAME", "_URL", "_URI", "_ADDR", "_ADDRESS"];
+
+/// The suffix of the env vars that Kubernetes injects for each service
+/// (e.g., `CART_SERVICE_HOST` for `cart`), which clients often refer to
+const SERVICE_HOST_SUFFIX: &str = "_SERVICE_HOST";
+
+/// An interaction between microservices inferred from the manifests, where
+/// the caller is unknown when the callee is reached from outside the workloads
+/// (e.g., through a VirtualService or an Ingress)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Interaction {
+    pub caller: Option<Host>,
+    pub callee: Host,
+}
+
+/// An endpoint written in an env var (or in a config entry) that is not reached
+/// through a k8s service, i.e., an IP address, the DNS name of a pod or the name
+/// of a workload that no service is named after
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HardcodedEndpoint {
+    /// the host, as written in the value
+    pub host: String,
+    /// the name of the k8s service reaching the endpoint (if known), as seen
+    /// from the namespace of the value
+    pub service: Option<String>,
+}
+
+/// It infers the microservices invoked by other microservices from the manifests
+pub fn invoked_services(ctx: &AnalysisContext) -> Vec<Host> {
+    let invoked: BTreeSet<Host> = interactions(ctx)
+        .into_iter()
+        .map(|i| i.callee)
+        .collect();
+
+    invoked.into_iter().collect()
+}
+
+/// It infers who calls whom from the manifests, by looking at the env vars of
+/// the workloads and at the entries of the ConfigMaps they consume (when they
+/// name a service, or hold its DNS name or URL), at the destinations of Istio
+/// VirtualServices and at the backends of Ingresses
+pub fn interactions(ctx: &AnalysisContext) -> Vec<Interaction> {
+    let mut interactions: BTreeSet<Interaction> = BTreeSet::new();
+
+    for workload in ctx.workloads() {
+        let namespace = ctx.namespace_of(workload);
+        let caller = Host { name: workload.metadata.name.clone(), namespace: namespace.to_owned() };
+
+        let mut entries: Vec<(String, String)> = workload.all_containers()
+            .iter()
+            .flat_map(|c| c.env.iter().flatten())
+            .filter_map(|env| env.value.as_ref().map(|value| (env.name.clone(), value.clone())))
+            .collect();
+        entries.extend(config_map_entries(ctx, workload));
+
+        for (name, value) in entries {
+            for host in referenced_hosts(&name, &value, namespace) {
+                // a microservice referring to itself does not invoke anything
+                interactions.extend(
+                    targets_of(ctx, &host)
+                        .into_iter()
+                        .filter(|callee| *callee != caller)
+                        .map(|callee| Interaction { caller: Some(caller.clone()), callee })
+                );
+            }
+        }
+    }
+
+    let mut reached: Vec<Host> = vec![];
+
+    for virtual_service in ctx.virtual_services() {
+        let destinations = virtual_service.spec.http
+            .iter()
+            .flatten()
+            .flat_map(|http| &http.route)
+            .map(|route| ctx.host_from(&route.destinations.host, virtual_service));
+
+        for host in destinations {
+            reached.extend(targets_of(ctx, &host));
+        }
+    }
+
+    for ingress in ctx.of_kind(&["Ingress"]) {
+        for backend in ingress_backends(&ingress.raw) {
+            reached.extend(targets_of(ctx, &ctx.host_from(&backend, ingress)));
+        }
+    }
+
+    interactions.extend(reached.into_iter().map(|callee| Interaction { caller: None, callee }));
+    interactions.into_iter().collect()
+}
+
+/// It returns the hosts referred to by an env var (or a config entry), given its
+/// name and value. Values are read as DNS names or URLs, while bare names are
+/// considered only if the name of the variable tells they are addresses.
+fn referenced_hosts(name: &str, value: &str, namespace: &str) -> Vec<Host> {
+    if let Some(service) = name.to_uppercase().strip_suffix(SERVICE_HOST_SUFFIX) {
+        let service = service.to_lowercase().replace('_', "-");
+        return vec![Host::resolve(&service, namespace)];
+    }
+
+    let is_address = is_address_name(name);
+
+    tokens(value)
+        .into_iter()
+        .filter_map(|(_, token)| (is_address || looks_like_address(token)).then(|| host_of(token)).flatten())
+        .map(|host| Host::resolve(host, namespace))
+        .collect()
+}
+
+/// It returns the endpoints that an env var (or a config entry) hardcodes, given
+/// its name and value, where the value is read from the objects of `namespace`.
+/// Loopback and unspecified addresses (e.g., those to bind to) are not endpoints.
+pub fn hardcoded_endpoints(
+    ctx: &AnalysisContext,
+    name: &str,
+    value: &str,
+    namespace: &str
+) -> Vec<HardcodedEndpoint> {
+    let is_address = is_address_name(name);
+    let mut endpoints: Vec<HardcodedEndpoint> = vec![];
+
+    for (_, token) in tokens(value) {
+        let Some(host) = address_host(token) else { continue };
+
+        let endpoint = match host.parse::<IpAddr>() {
+            Ok(ip) if ip.is_loopback() || ip.is_unspecified() => None,
+            // the cluster IP of a service is as stable as its name, but it is not portable
+            Ok(_) => Some(HardcodedEndpoint {
+                host: host.to_owned(),
+                service: ctx.services()
+                    .into_iter()
+                    .find(|s| {
+                        let cluster_ip = s.raw.get("spec").and_then(|spec| spec.get("clusterIP"));
+                        cluster_ip.and_then(|ip| ip.as_str()) == Some(host)
+                    })
+                    .map(|s| service_name(ctx, s, namespace)),
+            }),
+            // pods are reachable by DNS names made of their IP addresses
+            Err(_) if host.ends_with(".pod") || host.contains(".pod.") => Some(HardcodedEndpoint {
+                host: host.to_owned(),
+                service: None,
+            }),
+            Err(_) if !(is_address || looks_like_address(token)) || !is_dns_name(host) => None,
+            Err(_) => {
+                let target = Host::resolve(host, namespace);
+                let has_service = ctx.named(&target.name)
+                    .iter()
+                    .any(|m| m.kind == "Service" && ctx.namespace_of(m) == target.namespace);
+
+                ctx.workload_for(&target)
+                    .filter(|_| !has_service)
+                    .map(|workload| HardcodedEndpoint {
+                        host: host.to_owned(),
+                        service: ctx.services_selecting(workload)
+                            .first()
+                            .map(|s| service_name(ctx, s, namespace)),
+                    })
+            },
+        };
+
+        if let Some(endpoint) = endpoint.filter(|e| !endpoints.contains(e)) {
+            endpoints.push(endpoint);
+        }
+    }
+
+    endpoints
+}
+
+/// It rewrites a value so that its hardcoded endpoints are reached through their
+/// k8s services, provided that each of them is reached by a known service
+pub fn through_services(value: &str, endpoints: &[HardcodedEndpoint]) -> Option<String> {
+    let mut rewritten = String::new();
+    let mut copied = 0;
+
+    for (offset, token) in tokens(value) {
+        let Some(host) = address_host(token) else { continue };
+        let Some(endpoint) = endpoints.iter().find(|e| e.host == host) else { continue };
+
+        let start = offset + offset_in(token, host);
+        rewritten.push_str(&value[copied..start]);
+        rewritten.push_str(endpoint.service.as_deref()?);
+        copied = start + host.len();
+    }
+
+    rewritten.push_str(&value[copied..]);
+    Some(rewritten)
+}
+
+/// It rewrites a value (of the env var or config entry `name`) read from the objects
+/// of `namespace`, so that the addresses in it reaching the microservice at `from`
+/// reach the k8s service `to` instead. It returns `None` if no address reaches `from`.
+pub fn redirected(
+    ctx: &AnalysisContext,
+    name: &str,
+    value: &str,
+    namespace: &str,
+    from: &Host,
+    to: &str
+) -> Option<String> {
+    let is_address = is_address_name(name);
+    let mut rewritten = String::new();
+    let mut copied = 0;
+
+    for (offset, token) in tokens(value) {
+        if !is_address && !looks_like_address(token) { continue }
+        let Some(host) = host_of(token) else { continue };
+        if !targets_of(ctx, &Host::resolve(host, namespace)).contains(from) { continue }
+
+        let start = offset + offset_in(token, host);
+        rewritten.push_str(&value[copied..start]);
+        rewritten.push_str(to);
+        copied = start + host.len();
+    }
+
+    if copied == 0 {
+        return None;
+    }
+
+    rewritten.push_str(&value[copied..]);
+    Some(rewritten)
+}
+
+/// It returns the offset of `host` in `token`, of which it is a slice
+fn offset_in(token: &str, host: &str) -> usize {
+    host.as_ptr() as usize - token.as_ptr() as usize
+}
+
+/// It tells whether the name of an env var (or of a config entry) tells
+/// that its value is an address
+fn is_address_name(name: &str) -> bool {
+    let name = name.to_uppercase();
+    ADDRESS_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+fn looks_like_address(token: &str) -> bool {
+    token.contains("://") || token.contains('.') || token.contains(':')
+}
+
+/// It splits a value into the tokens that may be addresses, each with its offset
+fn tokens(value: &str) -> Vec<(usize, &str)> {
+    let is_separator = |c: char| c.is_whitespace() || matches!(c, ',' | ';' | '"' | '\'' | '=');
+
+    let mut tokens: Vec<(usize, &str)> = vec![];
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        if is_separator(c) {
+            if start < i {
+                tokens.push((start, &value[start..i]));
+            }
+            start = i + c.len_utf8();
+        }
+    }
+
+    if start < value.len() {
+        tokens.push((start, &value[start..]));
+    }
+
+    tokens
+}
+
+/// It extracts the host from a DNS name or a URL, e.g., `cart` from
+/// `http://user@cart:8080/api`, provided that it is a valid DNS name
+fn host_of(token: &str) -> Option<&str> {
+    address_host(token).filter(|host| is_dns_name(host))
+}
+
+/// It extracts the host from an address, e.g., `cart` from `http://user@cart:8080/api`
+/// or `10.0.0.7` from `10.0.0.7:8080`, whatever the host is
+fn address_host(token: &str) -> Option<&str> {
+    let address = token.split_once("://").map_or(token, |(_, rest)| rest);
+    let authority = address
+        .split(['/', '?', '#'])
+        .next()?;
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+
+    // IPv6 addresses are enclosed in brackets, unless they come without a port
+    if authority.parse::<IpAddr>().is_ok() {
+        return Some(authority);
+    }
+
+    match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next(),
+        None => authority.split(':').next(),
+    }
+    .filter(|host| !host.is_empty())
+}
+
+fn is_dns_name(host: &str) -> bool {
+    host.starts_with(|c: char| c.is_ascii_lowercase())
+        && host.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+}
+
+/// It names a k8s service as reached from the objects of `namespace`
+fn service_name(ctx: &AnalysisContext, service: &K8SManifest, namespace: &str) -> String {
+    let host = Host { name: service.metadata.name.clone(), namespace: ctx.namespace_of(service).to_owned() };
+
+    match host.namespace == namespace {
+        true => host.name,
+        false => host.to_string(),
+    }
+}
+
+/// It returns the microservices reached at `host`, i.e., the workloads selected
+/// by the service with such name or, if there's none, the workload named so
+fn targets_of(ctx: &AnalysisContext, host: &Host) -> Vec<Host> {
+    let service = ctx.named(&host.name)
+        .into_iter()
+        .find(|m| m.kind == "Service" && ctx.namespace_of(m) == host.namespace);
+
+    if let Some(service) = service {
+        let selected: Vec<Host> = ctx.selected_by(service)
+            .into_iter()
+            .map(|w| Host { name: w.metadata.name.clone(), namespace: ctx.namespace_of(w).to_owned() })
+            .collect();
+
+        if !selected.is_empty() {
+            return selected;
+        }
+    }
+
+    match ctx.workload_for(host) {
+        Some(_) => vec![host.clone()],
+        None => vec![],
+    }
+}
+
+/// It lists the (key, value) entries of the ConfigMaps consumed by a workload,
+/// either as env vars or as mounted volumes
+fn config_map_entries(ctx: &AnalysisContext, workload: &K8SManifest) -> Vec<(String, String)> {
+    consumed_config_maps(ctx, workload)
+        .into_iter()
+        .flat_map(data_entries)
+        .collect()
+}
+
+/// It returns the ConfigMaps consumed by a workload
+pub fn consumed_config_maps<'a>(ctx: &'a AnalysisContext, workload: &K8SManifest) -> Vec<&'a K8SManifest> {
+    let mut names: Vec<String> = vec![];
+    collect_config_map_refs(&workload.raw, &mut names);
+    names.sort();
+    names.dedup();
+
+    names
+        .iter()
+        .flat_map(|name| ctx.named(name))
+        .filter(|m| m.kind == "ConfigMap" && ctx.namespace_of(m) == ctx.namespace_of(workload))
+        .collect()
+}
+
+/// It lists the (key, value) entries of the data of a ConfigMap
+pub fn data_entries(config_map: &K8SManifest) -> Vec<(String, String)> {
+    config_map.raw
+        .get("data")
+        .and_then(|d| d.as_mapping())
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, value)| Some((key.as_str()?.to_owned(), value.as_str()?.to_owned())))
+        .collect()
+}
+
+/// It collects the names of the ConfigMaps referred to in a (part of a) manifest,
+/// i.e., by `configMapRef`, `configMapKeyRef` and `configMap` volumes
+fn collect_config_map_refs(node: &Value, names: &mut Vec<String>) {
+    match node {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                let is_ref = matches!(key.as_str(), Some("configMapRef" | "configMapKeyRef" | "configMap"));
+                let name = value.get("name").and_then(|n| n.as_str());
+
+                match (is_ref, name) {
+                    (true, Some(name)) => names.push(name.to_owned()),
+                    _ => collect_config_map_refs(value, names),
+                }
+            }
+        },
+        Value::Sequence(items) => items.iter().for_each(|item| collect_config_map_refs(item, names)),
+        _ => {}
+    }
+}
+
+/// It lists the names of the services an Ingress routes to, both in the
+/// `networking.k8s.io/v1` form and in the older `extensions/v1beta1` one
+pub fn ingress_backends(raw: &Value) -> Vec<String> {
+    let Some(spec) = raw.get("spec") else { return vec![] };
+
+    let paths = spec.get("rules")
+        .and_then(|r| r.as_sequence())
+        .into_iter()
+        .flatten()
+        .filter_map(|rule| rule.get("http")?.get("paths")?.as_sequence())
+        .flatten()
+        .filter_map(|path| path.get("backend"));
+
+    let defaults = ["defaultBackend", "backend"]
+        .into_iter()
+        .filter_map(|key| spec.get(key));
+
+    paths
+        .chain(defaults)
+        .filter_map(|backend| {
+            backend.get("service")
+                .and_then(|s| s.get("name"))
+                .or_else(|| backend.get("serviceName"))
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_owned())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, namespace: &str) -> Host {
+        Host { name: name.to_owned(), namespace: namespace.to_owned() }
+    }
+
+    #[test]
+    fn it_resolves_hosts_as_reached_from_a_namespace() {
+        assert_eq!(Host::resolve("cart", "shop"), host("cart", "shop"));
+        assert_eq!(Host::resolve("cart.orders", "shop"), host("cart", "orders"));
+        assert_eq!(Host::resolve("cart.orders.svc", "shop"), host("cart", "orders"));
+        assert_eq!(Host::resolve("cart.orders.svc.cluster.local", "shop"), host("cart", "orders"));
+        assert_eq!(Host::resolve("cart.orders.svc.cluster.local.", "shop"), host("cart", "orders"));
+    }
+
+    #[test]
+    fn it_extracts_hosts_from_addresses() {
+        assert_eq!(address_host("cart"), Some("cart"));
+        assert_eq!(address_host("cart:8080"), Some("cart"));
+        assert_eq!(address_host("http://user@cart.orders:8080/api?q=1"), Some("cart.orders"));
+        assert_eq!(address_host("mongodb://cart-db.orders.svc.cluster.local:27017/db"), Some("cart-db.orders.svc.cluster.local"));
+        assert_eq!(address_host("10.0.0.7:8080"), Some("10.0.0.7"));
+        assert_eq!(address_host("http://[fd00::7]:8080/"), Some("fd00::7"));
+        assert_eq!(address_host("fd00::7"), Some("fd00::7"));
+        assert_eq!(address_host("http://:8080"), None);
+
+        // addresses are hosts only if they are DNS names
+        assert_eq!(host_of("http://cart:8080"), Some("cart"));
+        assert_eq!(host_of("10.0.0.7:8080"), None);
+        assert_eq!(host_of("http://Cart:8080"), None);
+    }
+
+    #[test]
+    fn it_splits_values_into_tokens() {
+        assert_eq!(
+            tokens("--db=mongodb://cart-db:27017, cache:6379;'queue'"),
+            [(0, "--db"), (5, "mongodb://cart-db:27017"), (30, "cache:6379"), (42, "queue")]
+        );
+    }
+
+    #[test]
+    fn it_finds_the_hosts_referenced_by_env_vars() {
+        assert_eq!(referenced_hosts("CART_URL", "http://cart:8080/api", "shop"), [host("cart", "shop")]);
+        assert_eq!(referenced_hosts("CART_HOST", "cart", "shop"), [host("cart", "shop")]);
+        assert_eq!(referenced_hosts("DB", "cart-db.orders:27017", "shop"), [host("cart-db", "orders")]);
+        assert_eq!(
+            referenced_hosts("UPSTREAMS", "http://cart.orders.svc.cluster.local:80 users.shop.svc:8080", "shop"),
+            [host("cart", "orders"), host("users", "shop")]
+        );
+        assert_eq!(referenced_hosts("CART_SERVICE_HOST", "10.0.0.7", "shop"), [host("cart", "shop")]);
+
+        // bare names are addresses only if the name of the var tells so
+        assert!(referenced_hosts("MODE", "prod", "shop").is_empty());
+    }
+}
@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
 
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use serde_with::skip_serializing_none;
 
@@ -8,22 +10,86 @@ use serde_with::skip_serializing_none;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Port {
+    pub name: Option<String>,
+
     pub hostPort: Option<i32>,
     
     #[serde(rename = "containerPort")]
-    pub container_port: Option<i32>
+    pub container_port: Option<i32>,
+
+    pub protocol: Option<String>
+}
+
+impl Port {
+    /// It tells whether the port is the one a service forwards to as `target`
+    pub fn is_target_of(&self, target: &PortRef) -> bool {
+        match target {
+            PortRef::Number(number) => self.container_port == Some(*number),
+            PortRef::Name(name) => self.name.as_ref() == Some(name),
+        }
+    }
+}
+
+/// A port of the pods as referred to by a service, i.e., by number or by name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PortRef {
+    Number(i32),
+    Name(String),
 }
 
+impl fmt::Display for PortRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortRef::Number(number) => write!(f, "{}", number),
+            PortRef::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServicePort {
+    pub name: Option<String>,
+
+    pub protocol: Option<String>,
+
+    pub port: Option<i32>,
+
+    #[serde(rename = "targetPort")]
+    pub target_port: Option<PortRef>,
+
+    #[serde(rename = "nodePort")]
+    pub node_port: Option<i32>
+}
+
+impl ServicePort {
+    /// It returns the port of the pods the traffic is forwarded to, i.e.,
+    /// `targetPort` or, if not given, the same number of `port`
+    pub fn target(&self) -> Option<PortRef> {
+        self.target_port
+            .clone()
+            .or(self.port.map(PortRef::Number))
+    }
+}
+
+/// The service types exposing the selected pods outside of the cluster
+pub const EXTERNAL_SERVICE_TYPES: [&str; 2] = ["NodePort", "LoadBalancer"];
+
 #[derive(Debug)]
 pub struct Microservice {
     pub has_service: bool,
-    pub has_direct_access: bool
+    pub has_direct_access: bool,
+    /// the NodePort or LoadBalancer services exposing it outside of the cluster
+    pub external_services: Vec<String>
 }
 
+#[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Limit {
-    pub cpu: String,
-    pub memory: String
+    // quantities can be written both as numbers and as strings
+    pub cpu: Option<Value>,
+    pub memory: Option<Value>
 }
 
 #[skip_serializing_none]
@@ -61,7 +127,8 @@ pub struct Container {
     #[serde(rename = "securityContext")]
     pub security_context: Option<SecurityContext>,
     
-    pub image: String,
+    /// the image may be left to be set afterwards (e.g., by Kustomize `images`)
+    pub image: Option<String>,
     pub ports: Option<Vec<Port>>,
     
     #[serde(rename = "readinessProbe")]
@@ -78,9 +145,19 @@ pub struct Container {
     pub env: Option<Vec<Env>>
 }
 
+#[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Volume {
     pub name: String,
+
+    #[serde(rename = "persistentVolumeClaim")]
+    pub persistent_volume_claim: Option<PersistentVolumeClaimSource>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistentVolumeClaimSource {
+    #[serde(rename = "claimName")]
+    pub claim_name: String,
 }
 
 #[skip_serializing_none]
@@ -90,6 +167,7 @@ pub struct TemplateSpec {
     pub initContainers: Option<Vec<Container>>,
     pub containers: Option<Vec<Container>>,
     pub volumes: Option<Vec<Volume>>,
+    pub hostNetwork: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,23 +178,93 @@ pub struct Template {
     pub metadata: Option<MetadataTemplate>
 }
 
-#[skip_serializing_none]
+/// The template of the jobs run by a CronJob
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Labels {
-    pub app: Option<String>,
-    pub service: Option<String>,
-    pub name: Option<String>,
+pub struct JobTemplate {
+    #[serde(default)]
+    pub spec: Spec,
+}
+
+/// The labels of an object, whose values are all read as strings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Labels(pub BTreeMap<String, String>);
+
+impl<'de> Deserialize<'de> for Labels {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // unquoted values (e.g., `version: 2`) are read as the strings they stand for
+        let labels: Option<BTreeMap<String, Value>> = Option::deserialize(deserializer)?;
+
+        Ok(Labels(
+            labels
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|(key, value)| scalar_string(&value).map(|value| (key, value)))
+                .collect()
+        ))
+    }
+}
+
+impl Labels {
+    /// It lists the (key, value) pairs of the labels
+    pub fn pairs(&self) -> Vec<(&str, &str)> {
+        self.0
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    /// It tells whether all the given labels are among these ones
+    pub fn contains_all(&self, labels: &Labels) -> bool {
+        labels.0.iter().all(|(k, v)| self.0.get(k) == Some(v))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The selector of the pods managed by a workload
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LabelSelector {
+    #[serde(rename = "matchLabels", default)]
+    pub match_labels: Labels,
+
+    #[serde(rename = "matchExpressions", default)]
+    pub match_expressions: Vec<LabelSelectorRequirement>,
 }
 
-#[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Selector {
-    pub service: Option<String>,
-    
-    #[serde(rename = "matchLabels")]
-    pub match_labels: Option<Labels>,
-    
-    pub app: Option<String>
+pub struct LabelSelectorRequirement {
+    pub key: String,
+    pub operator: String,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+impl LabelSelector {
+    /// It returns the labels that all the selected pods carry, i.e., those
+    /// in `matchLabels` and those required to be `In` a single value
+    pub fn guaranteed_labels(&self) -> Labels {
+        let mut labels = self.match_labels.clone();
+
+        for requirement in &self.match_expressions {
+            if let ("In", [value]) = (requirement.operator.as_str(), requirement.values.as_slice()) {
+                labels.0.insert(requirement.key.clone(), value.clone());
+            }
+        }
+
+        labels
+    }
+}
+
+fn scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
 }
 
 #[skip_serializing_none]
@@ -165,17 +313,20 @@ pub struct Destination {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RouteSpec {
+    #[serde(alias = "destination")]
     pub destinations: Destination
 }
 
+#[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HttpSpec {
+    #[serde(default)]
     pub route: Vec<RouteSpec>,
-    pub timeout: String
+    pub timeout: Option<String>
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Spec {
     #[serde(rename = "serviceAccountName")]
@@ -194,10 +345,18 @@ pub struct Spec {
     pub volumes: Option<Vec<Volume>>,
     
     pub template: Option<Template>,
+
+    #[serde(rename = "jobTemplate")]
+    pub job_template: Option<Box<JobTemplate>>,
     
     pub hostNetwork: Option<bool>,
     
-    pub selector: Option<HashMap<String, Value>>,
+    pub selector: Option<BTreeMap<String, Value>>,
+
+    pub ports: Option<Vec<ServicePort>>,
+
+    #[serde(rename = "type")]
+    pub service_type: Option<String>,
     
     pub hosts: Option<Vec<String>>,
     
@@ -212,16 +371,22 @@ pub struct Spec {
     pub restartPolicy: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Metadata { 
+    // objects named through `generateName` have no name until they are created
+    #[serde(default)]
     pub name: String,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub labels: Option<Labels>
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetadataTemplate {
+    #[serde(default)]
     pub labels: Labels
 }
 
@@ -231,17 +396,218 @@ pub struct K8SManifest {
     #[serde(rename = "apiVersion")]
     pub api_version: String,
     pub kind: String,
+    #[serde(default)]
     pub metadata: Metadata,
+    #[serde(default)]
     pub spec: Spec,
+
+    #[serde(skip)]
+    pub source: Option<Source>,
+
+    /// the whole YAML document declaring the object, unknown fields included
+    #[serde(skip)]
+    pub raw: serde_yaml::Value,
+}
+
+/// The kinds of the objects running pods, i.e., the ones declaring containers
+pub const WORKLOAD_KINDS: [&str; 7] = ["Pod", "Deployment", "StatefulSet", "DaemonSet", "ReplicaSet", "Job", "CronJob"];
+
+/// The kinds whose spec is inspected by the smell checks
+pub const TYPED_KINDS: [&str; 10] = [
+    "Pod", "Deployment", "StatefulSet", "DaemonSet", "ReplicaSet", "Job", "CronJob",
+    "Service", "VirtualService", "DestinationRule",
+];
+
+/// The pod run by a workload, whether declared by a pod or by a pod template
+pub struct PodSpec<'a> {
+    pub containers: &'a [Container],
+    pub volumes: &'a [Volume],
+    pub host_network: bool,
+}
+
+impl K8SManifest {
+    /// It parses a k8s object from its YAML document. Only the objects of
+    /// `TYPED_KINDS` get their spec parsed: any other object is kept as a
+    /// generic document, i.e., with its kind and metadata only.
+    pub fn from_document(raw: serde_yaml::Value) -> Result<Self, serde_yaml::Error> {
+        let mut typed = raw.clone();
+
+        let is_typed = raw.get("kind")
+            .and_then(|kind| kind.as_str())
+            .is_some_and(|kind| TYPED_KINDS.contains(&kind));
+
+        if !is_typed {
+            if let Some(mapping) = typed.as_mapping_mut() {
+                mapping.remove("spec");
+            }
+        }
+
+        let mut manifest: K8SManifest = serde_yaml::from_value(typed)?;
+        manifest.raw = raw;
+        Ok(manifest)
+    }
+
+    /// It tells whether the object runs pods
+    pub fn is_workload(&self) -> bool {
+        WORKLOAD_KINDS.contains(&self.kind.as_str())
+    }
+
+    /// It returns the pod template of a workload, including the one nested
+    /// in the job template of a CronJob
+    pub fn pod_template(&self) -> Option<&Template> {
+        match &self.spec.job_template {
+            Some(job_template) => job_template.spec.template.as_ref(),
+            None => self.spec.template.as_ref(),
+        }
+    }
+
+    /// It returns the pod run by a workload, whatever its kind
+    pub fn pod_spec(&self) -> Option<PodSpec<'_>> {
+        if !self.is_workload() {
+            return None;
+        }
+
+        if self.kind == "Pod" {
+            return Some(PodSpec {
+                containers: self.spec.containers.as_deref().unwrap_or_default(),
+                volumes: self.spec.volumes.as_deref().unwrap_or_default(),
+                host_network: self.spec.hostNetwork.unwrap_or(false),
+            });
+        }
+
+        self.pod_template().map(|template| PodSpec {
+            containers: template.spec.containers.as_deref().unwrap_or_default(),
+            volumes: template.spec.volumes.as_deref().unwrap_or_default(),
+            host_network: template.spec.hostNetwork
+                .or(self.spec.hostNetwork)
+                .unwrap_or(false),
+        })
+    }
+
+    /// It returns the labels of the pods run by a workload, i.e., the labels of
+    /// its pod template together with those guaranteed by its selector
+    pub fn pod_labels(&self) -> Labels {
+        if self.kind == "Pod" {
+            return self.metadata.labels.clone().unwrap_or_default();
+        }
+
+        let mut labels = self.workload_selector()
+            .map(|s| s.guaranteed_labels())
+            .unwrap_or_default();
+
+        if let Some(metadata) = self.pod_template().and_then(|t| t.metadata.as_ref()) {
+            labels.0.extend(metadata.labels.0.clone());
+        }
+
+        labels
+    }
+
+    /// It returns the selector of the pods managed by a workload, if any
+    pub fn workload_selector(&self) -> Option<LabelSelector> {
+        let selector = match &self.spec.job_template {
+            Some(job_template) => job_template.spec.selector.as_ref(),
+            None => self.spec.selector.as_ref(),
+        }?;
+
+        let selector = Value::Object(selector.clone().into_iter().collect());
+        serde_json::from_value(selector).ok()
+    }
+
+    /// It returns the labels a service selects its pods by. Services with no
+    /// selector (or with an empty one) select no pod at all.
+    pub fn service_selector(&self) -> Option<Labels> {
+        let selector = Labels(
+            self.spec.selector
+                .iter()
+                .flatten()
+                .filter_map(|(key, value)| scalar_string(value).map(|value| (key.clone(), value)))
+                .collect()
+        );
+
+        (!selector.is_empty()).then_some(selector)
+    }
+
+    /// It tells whether the object is a service exposing its pods outside of
+    /// the cluster, i.e., a NodePort or LoadBalancer service
+    pub fn is_external_service(&self) -> bool {
+        self.kind == "Service" && self.spec.service_type
+            .as_deref()
+            .is_some_and(|t| EXTERNAL_SERVICE_TYPES.contains(&t))
+    }
+
+    /// It lists the containers of the pod run by a workload
+    pub fn all_containers(&self) -> Vec<&Container> {
+        self.pod_spec()
+            .map(|pod| pod.containers.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// It lists the names of the persistent volume claims mounted by the pod run by a workload
+    pub fn claimed_volumes(&self) -> Vec<&str> {
+        self.pod_spec()
+            .map(|pod| pod.volumes
+                .iter()
+                .filter_map(|v| v.persistent_volume_claim.as_ref())
+                .map(|claim| claim.claim_name.as_str())
+                .collect())
+            .unwrap_or_default()
+    }
+}
+
+/// The namespace of the objects declared without one, unless configured otherwise
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// A service as reached through the cluster DNS, i.e., by its name and namespace
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Host {
+    pub name: String,
+    pub namespace: String,
+}
+
+impl Host {
+    /// It resolves a host name as reached from the objects of `namespace`. Hosts can
+    /// be given as `svc`, `svc.ns`, `svc.ns.svc` or `svc.ns.svc.cluster.local`,
+    /// where `svc` alone refers to the service `svc` in `namespace` itself.
+    pub fn resolve(host: &str, namespace: &str) -> Self {
+        let host = host.trim_end_matches('.');
+        let host = host.strip_suffix(".cluster.local").unwrap_or(host);
+        let host = host.strip_suffix(".svc").unwrap_or(host);
+
+        match host.split_once('.') {
+            Some((name, namespace)) => Host { name: name.to_owned(), namespace: namespace.to_owned() },
+            None => Host { name: host.to_owned(), namespace: namespace.to_owned() },
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.name, self.namespace)
+    }
+}
+
+/// It tells where a manifest has been read from
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    /// the position of the manifest among the documents of `path`
+    pub document: usize,
+    /// the line the manifest starts at in `path`
+    pub line: usize,
+}
+
+impl Source {
+    /// It tells whether the source is the given document of the given file
+    pub fn is_at(&self, file: &str, document: usize) -> bool {
+        self.document == document && self.path.display().to_string() == file
+    }
 }
 
 pub fn get_patterns() -> Vec<String> {
-    let owned_vec = vec![
+    vec![
         "sidecar",
         "ambassador",
         "adapter",
     ].into_iter()
-        .map(|s| s.to_owned()).collect();
-
-    owned_vec
+        .map(|s| s.to_owned()).collect()
 }
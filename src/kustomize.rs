@@ -0,0 +1,342 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_with::skip_serializing_none;
+use serde_yaml::Value;
+
+use crate::context::AnalysisContext;
+use crate::error::Error;
+use crate::k8s_types::K8SManifest;
+use crate::refactor::{ChangeSet, ManifestChange};
+use crate::yaml_editor::{self, Edit, Step};
+use crate::yaml_handler;
+
+const KUSTOMIZATION_FILES: [&str; 3] = ["kustomization.yaml", "kustomization.yml", "Kustomization"];
+
+#[derive(Debug, Serialize)]
+struct Kustomization {
+    #[serde(rename = "apiVersion")]
+    api_version: String,
+    kind: String,
+    resources: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    patches: Vec<Patch>,
+}
+
+/// A JSON6902 patch, together with the resource it applies to
+#[derive(Debug, Serialize)]
+struct Patch {
+    target: Target,
+    patch: String,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Serialize)]
+struct Target {
+    group: Option<String>,
+    version: String,
+    kind: String,
+    name: String,
+    namespace: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Serialize)]
+struct Operation {
+    op: &'static str,
+    path: String,
+    value: Option<Value>,
+}
+
+/// It writes to `overlay_dir` a Kustomize overlay applying the changes on top of
+/// the manifests folders, which are left untouched: the updated manifests become
+/// JSON6902 patches, while the generated resources are added to the overlay, in
+/// place of the manifest files whose resources they all redeclare
+pub fn write_overlay(ctx: &AnalysisContext, changes: &ChangeSet, overlay_dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(overlay_dir).map_err(|e| Error::io(overlay_dir, e))?;
+
+    let mut generated: Vec<K8SManifest> = vec![];
+    for change in changes.files.iter().filter(|c| c.generated) {
+        generated.extend(yaml_handler::parse_documents(&change.path, &change.updated)?);
+    }
+
+    let is_redeclared = |manifest: &K8SManifest| generated
+        .iter()
+        .any(|g| g.kind == manifest.kind
+            && g.metadata.name == manifest.metadata.name
+            && ctx.namespace_of(g) == ctx.namespace_of(manifest));
+
+    let mut resources: Vec<String> = vec![];
+    for dir in &ctx.manifest_dirs {
+        resources.extend(base_resources(ctx, dir, overlay_dir, &is_redeclared)?);
+    }
+
+    for change in changes.files.iter().filter(|c| c.generated) {
+        let Some(filename) = change.path.file_name() else { continue };
+        let path = overlay_dir.join(filename);
+
+        fs::write(&path, &change.updated).map_err(|e| Error::io(&path, e))?;
+        resources.push(filename.to_string_lossy().to_string());
+    }
+
+    // the manifests left out of the overlay are not patched
+    let kustomization_path = overlay_dir.join(KUSTOMIZATION_FILES[0]);
+    let patches = changes.manifests
+        .iter()
+        .filter(|change| !is_redeclared(&change.original))
+        .map(|change| to_patch(&kustomization_path, change))
+        .collect::<Result<Vec<Patch>, Error>>()?;
+
+    let kustomization = Kustomization {
+        api_version: String::from("kustomize.config.k8s.io/v1beta1"),
+        kind: String::from("Kustomization"),
+        resources,
+        patches,
+    };
+
+    let yaml = serde_yaml::to_string(&kustomization)
+        .map_err(|e| Error::serialize(&kustomization_path, e))?;
+    fs::write(&kustomization_path, yaml).map_err(|e| Error::io(&kustomization_path, e))
+}
+
+/// It lists the resources of the overlay pointing to a manifests folder: the
+/// folder itself if it is a kustomization, each of its analyzed manifests otherwise,
+/// except for those whose resources are all redeclared by the generated ones
+fn base_resources(
+    ctx: &AnalysisContext,
+    dir: &Path,
+    overlay_dir: &Path,
+    is_redeclared: &dyn Fn(&K8SManifest) -> bool
+) -> Result<Vec<String>, Error> {
+    let declared_in = |path: &Path| -> Vec<&K8SManifest> {
+        ctx.manifests
+            .iter()
+            .filter(|m| m.source.as_ref().is_some_and(|s| s.path.starts_with(path)))
+            .collect()
+    };
+
+    // a resource cannot be declared twice, and it can only be left out with its file
+    let redeclared = |path: &Path| -> Result<bool, Error> {
+        let manifests = declared_in(path);
+        match manifests.iter().filter(|m| is_redeclared(m)).count() {
+            0 => Ok(false),
+            n if n == manifests.len() && path.is_file() => Ok(true),
+            _ => {
+                let reason = "some of its resources are redeclared by the refactorings, \
+                    hence they cannot be left out of the overlay";
+                Err(Error::io(path, io::Error::new(io::ErrorKind::AlreadyExists, reason)))
+            }
+        }
+    };
+
+    if KUSTOMIZATION_FILES.iter().any(|f| dir.join(f).exists()) {
+        redeclared(dir)?;
+        return Ok(vec![relative_to(dir, overlay_dir)?]);
+    }
+
+    eprintln!(
+        "[!] {} is not a kustomization: its manifests are listed one by one, \
+        which requires kustomize to be run with --load-restrictor LoadRestrictionsNone",
+        dir.display()
+    );
+
+    let mut manifests = yaml_handler::manifest_files(&[dir.to_path_buf()], &ctx.config.ignored_manifests)?;
+    manifests.sort();
+
+    let mut resources = vec![];
+    for path in manifests.iter().filter(|path| !path.starts_with(overlay_dir)) {
+        if !redeclared(path)? {
+            resources.push(relative_to(path, overlay_dir)?);
+        }
+    }
+
+    Ok(resources)
+}
+
+/// It returns the path of `path` relative to the folder `from`, as used in kustomizations
+fn relative_to(path: &Path, from: &Path) -> Result<String, Error> {
+    let path = fs::canonicalize(path).map_err(|e| Error::io(path, e))?;
+    let from = fs::canonicalize(from).map_err(|e| Error::io(from, e))?;
+
+    let common = path.components()
+        .zip(from.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in from.components().skip(common) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common) {
+        relative.push(component);
+    }
+
+    if relative.as_os_str().is_empty() {
+        return Ok(String::from("."));
+    }
+
+    Ok(relative.display().to_string().replace('\\', "/"))
+}
+
+/// It turns the change of a manifest into a JSON6902 patch targeting it
+fn to_patch(kustomization_path: &Path, change: &ManifestChange) -> Result<Patch, Error> {
+    let manifest = &change.original;
+
+    let operations = match yaml_editor::edits(&manifest.raw, &change.updated) {
+        Some(edits) => edit_operations(&manifest.raw, &edits),
+        // changes other than removals and replacements are applied by replacing the whole spec
+        None => vec![Operation {
+            op: "replace",
+            path: String::from("/spec"),
+            value: change.updated.get("spec").cloned(),
+        }],
+    };
+
+    let (group, version) = match manifest.api_version.split_once('/') {
+        Some((group, version)) => (Some(group.to_owned()), version.to_owned()),
+        None => (None, manifest.api_version.clone()),
+    };
+
+    let namespace = manifest.raw
+        .get("metadata")
+        .and_then(|m| m.get("namespace"))
+        .and_then(|n| n.as_str())
+        .map(|n| n.to_owned());
+
+    Ok(Patch {
+        target: Target {
+            group,
+            version,
+            kind: manifest.kind.clone(),
+            name: manifest.metadata.name.clone(),
+            namespace,
+        },
+        patch: serde_yaml::to_string(&operations).map_err(|e| Error::serialize(kustomization_path, e))?,
+    })
+}
+
+/// It turns the edits into JSON6902 operations. Edits are applied
+/// from the last one, so that each removal leaves the indexes of the
+/// following edits unchanged, and each sequence item is tested to be the
+/// expected one (by its name) before changing anything in it.
+fn edit_operations(original: &Value, edits: &[Edit]) -> Vec<Operation> {
+    let mut operations: Vec<Operation> = vec![];
+
+    for edit in edits.iter().rev() {
+        let path = match edit {
+            Edit::Remove(path) | Edit::Replace(path, _) | Edit::Append(path, _) => path,
+        };
+
+        let mut node = Some(original);
+        let mut pointer = String::new();
+
+        for step in path {
+            node = match step {
+                Step::Key(key) => {
+                    pointer.push_str(&format!("/{}", key.replace('~', "~0").replace('/', "~1")));
+                    node.and_then(|n| n.get(key.as_str()))
+                },
+                Step::Index(index) => {
+                    pointer.push_str(&format!("/{}", index));
+                    node.and_then(|n| n.get(index))
+                },
+            };
+
+            let name = node.and_then(|n| n.get("name"));
+            let test_path = format!("{}/name", pointer);
+            let is_tested = operations.iter().any(|o| o.op == "test" && o.path == test_path);
+
+            if let (Step::Index(_), Some(name), false) = (step, name, is_tested) {
+                operations.push(Operation { op: "test", path: test_path, value: Some(name.clone()) });
+            }
+        }
+
+        operations.push(match edit {
+            Edit::Remove(_) => Operation { op: "remove", path: pointer, value: None },
+            Edit::Replace(_, value) => Operation { op: "replace", path: pointer, value: Some(value.clone()) },
+            Edit::Append(_, value) => Operation { op: "add", path: format!("{}/-", pointer), value: Some(value.clone()) },
+        });
+    }
+
+    operations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operations(original: &str, update: impl FnOnce(&mut Value)) -> Vec<(&'static str, String, Option<Value>)> {
+        let original: Value = serde_yaml::from_str(original).unwrap();
+        let mut updated = original.clone();
+        update(&mut updated);
+
+        let edits = yaml_editor::edits(&original, &updated).unwrap();
+        edit_operations(&original, &edits)
+            .into_iter()
+            .map(|o| (o.op, o.path, o.value))
+            .collect()
+    }
+
+    const DEPLOYMENT: &str = "\
+spec:
+  template:
+    spec:
+      hostNetwork: true
+      containers:
+        - name: cart
+          ports:
+            - containerPort: 8080
+              hostPort: 8080
+          env:
+            - name: DB
+              value: 10.0.0.7:27017
+";
+
+    #[test]
+    fn it_turns_removals_into_remove_operations() {
+        let ops = operations(DEPLOYMENT, |d| {
+            let spec = &mut d["spec"]["template"]["spec"];
+            spec.as_mapping_mut().unwrap().remove("hostNetwork");
+            spec["containers"][0]["ports"][0].as_mapping_mut().unwrap().remove("hostPort");
+        });
+
+        assert_eq!(ops, [
+            ("test", String::from("/spec/template/spec/containers/0/name"), Some(Value::from("cart"))),
+            ("remove", String::from("/spec/template/spec/containers/0/ports/0/hostPort"), None),
+            ("remove", String::from("/spec/template/spec/hostNetwork"), None),
+        ]);
+    }
+
+    #[test]
+    fn it_turns_replacements_into_tested_replace_operations() {
+        let ops = operations(DEPLOYMENT, |d| {
+            d["spec"]["template"]["spec"]["containers"][0]["env"][0]["value"] = Value::from("cart-db:27017");
+        });
+
+        assert_eq!(ops, [
+            ("test", String::from("/spec/template/spec/containers/0/name"), Some(Value::from("cart"))),
+            ("test", String::from("/spec/template/spec/containers/0/env/0/name"), Some(Value::from("DB"))),
+            ("replace", String::from("/spec/template/spec/containers/0/env/0/value"), Some(Value::from("cart-db:27017"))),
+        ]);
+    }
+
+    #[test]
+    fn it_turns_appended_items_into_add_operations() {
+        let ops = operations("metadata:\n  finalizers:\n    - a/b\n", |d| {
+            d["metadata"]["finalizers"].as_sequence_mut().unwrap().push(Value::from("c/d"));
+        });
+
+        assert_eq!(ops, [("add", String::from("/metadata/finalizers/-"), Some(Value::from("c/d")))]);
+    }
+
+    #[test]
+    fn it_escapes_keys_in_pointers() {
+        let ops = operations("metadata:\n  annotations:\n    example.com/a~b: x\n", |d| {
+            d["metadata"]["annotations"].as_mapping_mut().unwrap().remove("example.com/a~b");
+        });
+
+        assert_eq!(ops, [("remove", String::from("/metadata/annotations/example.com~1a~0b"), None)]);
+    }
+}
@@ -3,55 +3,139 @@ mod yaml_handler;
 mod freshener;
 mod cmd_handler;
 mod config_type;
-
-use std::env;
-
-use crate::{k8s_types::*};
-use crate::{cmd_handler::CMD};
+mod context;
+mod report;
+mod refactor;
+mod output;
+mod error;
+mod yaml_editor;
+mod kustomize;
+mod inference;
+mod graph;
+mod microtosca;
+
+use std::panic;
+use std::process::{self, ExitCode};
+
+use crate::cmd_handler::{AnalyzeOptions, Cli, CMD};
+use crate::context::AnalysisContext;
+use crate::error::Error;
+use crate::output::OutputFormat;
+use clap::Parser;
 use colored::Colorize;
 
-fn main() {
+/// Exit code when no (failing) smell has been found
+const EXIT_CLEAN: u8 = 0;
+/// Exit code when some smell selected by `--fail-on` has been found
+const EXIT_SMELLS_FOUND: u8 = 1;
+/// Exit code when the tool could not complete its job
+const EXIT_TOOL_ERROR: u8 = 2;
+
+fn main() -> ExitCode {
+    // unexpected failures must not be mistaken for smells found
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        process::exit(EXIT_TOOL_ERROR.into());
+    }));
+
+    let cli = Cli::parse();
+
+    let done = match &cli.command {
+        CMD::Analyze(options) => return exit_with(analyze(&cli, options)),
+        CMD::ExportGraph { format, output } =>
+            cmd_handler::export_graph(&cli, *format, output.as_deref()),
+        CMD::ExportMicrotosca { output } => cmd_handler::export_microtosca(&cli, output.as_deref()),
+        CMD::ImportMicrotosca { model } => cmd_handler::import_microtosca(&cli, model),
+        CMD::Split => cmd_handler::split(&cli),
+        CMD::ListKnownImages => cmd_handler::list_known_images(&cli),
+        CMD::ListManifestsIgnore => cmd_handler::list_manifest_ignore(&cli),
+        CMD::AddKnownImage { name, image, kind } =>
+            cmd_handler::add_known_image(&cli, name, image, *kind),
+        CMD::AddManifestIgnore { filename } => cmd_handler::add_manifest_ignore(&cli, filename),
+        CMD::DeleteKnownImage { name } => cmd_handler::delete_known_image(&cli, name),
+        CMD::DeleteManifestIgnore { filename } => cmd_handler::delete_manifest_ignore(&cli, filename),
+    };
+
+    exit_with(done.map(|done| if done { EXIT_CLEAN } else { EXIT_TOOL_ERROR }))
+}
+
+/// It turns the outcome of a command into the exit code of the tool,
+/// reporting the error that made it fail (if any)
+fn exit_with(outcome: Result<u8, Error>) -> ExitCode {
+    match outcome {
+        Ok(code) => ExitCode::from(code),
+        Err(error) => {
+            eprintln!("{} {}", "[X]".red().bold(), error);
+            ExitCode::from(EXIT_TOOL_ERROR)
+        }
+    }
+}
 
-    let args: Vec<String> = env::args().collect();
+fn analyze(cli: &Cli, options: &AnalyzeOptions) -> Result<u8, Error> {
+    let is_text = options.output_format == OutputFormat::Text;
 
-    if args.len() <= 1 {
-        println!("Please type commands like analyze, list-known-images, ...");
-        return;
+    if is_text {
+        println!("{}\n", "*** K8S FRESHENER ***".blue().bold());
+        println!("{}", "####### Parsing ########".bold());
     }
 
-    let command = CMD::from_str(args[1].as_str());
+    let ctx = AnalysisContext::load(
+        &cli.manifests,
+        &cli.config,
+        &cli.known_images,
+        cli.skip_invalid,
+        is_text
+    )?;
+
+    let report = freshener::check_all(&ctx);
+
+    if is_text { println!("{}", "### Start Inspection ###".bold()); }
 
-    match command {
-        CMD::Analyze => {
-            let mut manifests: Vec<K8SManifest>;
-                
-            println!("{}\n", format!("*** K8S FRESHENER ***").blue().bold());
+    output::emit(&report, options.output_format);
 
-            println!("{}", format!("####### Parsing ########").bold());
+    let mut is_incomplete = false;
+    if options.is_to_refactor {
+        let changes = refactor::plan(&ctx, &report)?;
 
-            manifests = startup(true);
-            println!("{}", format!("### Start Inspection ###").bold());
+        if options.dry_run {
+            // the report stays the only content of stdout in machine readable formats
+            if is_text { print!("{}", changes.to_patch()) } else { eprint!("{}", changes.to_patch()) }
+        }
 
-            let is_to_refactor = args.len() >= 3 && args[2].clone() == "-s";
+        if let Some(patch) = &options.patch {
+            changes.save_patch(patch)?;
+        }
 
-            freshener::check_independent_depl(&manifests, is_to_refactor);
+        if let Some(out_dir) = &options.out_dir {
+            changes.apply_to_dir(&ctx.manifest_dirs, out_dir)?;
+        }
 
-            manifests = startup(false);
-            freshener::check_no_apigateway(&manifests, is_to_refactor);
+        if let Some(overlay) = &options.overlay {
+            kustomize::write_overlay(&ctx, &changes, overlay)?;
+        }
 
-            manifests = startup(false);
-            freshener::check_endpoint_based_interaction(&manifests, is_to_refactor);
+        // the manifests are rewritten only if the refactorings are not sent elsewhere
+        let is_in_place = !options.dry_run
+            && options.patch.is_none()
+            && options.out_dir.is_none()
+            && options.overlay.is_none();
 
-            manifests = startup(false);
-            freshener::check_wobbly_interaction(&manifests, is_to_refactor);
+        if is_in_place {
+            changes.apply()?;
+        }
 
-            println!("{}", format!("### Inspection Ended ###").bold());
-        },
-        _ =>  println!("Unrecognized command")
+        is_incomplete = changes.skipped > 0;
     }
 
-}
+    if is_text { println!("{}", "### Inspection Ended ###".bold()); }
 
-fn startup(log: bool) -> Vec<K8SManifest> {
-    return yaml_handler::parse_manifests(log);
+    // the refactorings that could not be made must not go unnoticed
+    if is_incomplete {
+        Ok(EXIT_TOOL_ERROR)
+    } else if report.fails_on(&options.fail_on) {
+        Ok(EXIT_SMELLS_FOUND)
+    } else {
+        Ok(EXIT_CLEAN)
+    }
 }
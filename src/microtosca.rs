@@ -0,0 +1,404 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::config_type::{Config, ImageKind, NodeConfigElement};
+use crate::context::AnalysisContext;
+use crate::graph::{self, NodeKind};
+use crate::inference;
+use crate::k8s_types::*;
+
+const TOSCA_VERSION: &str = "micro_tosca_yaml_1.1";
+const MICRO_TOSCA_URI: &str = "https://di-unipi-socc.github.io/microTOSCA/1.1/microTOSCA.yaml";
+
+const SERVICE: &str = "micro.nodes.Service";
+const DATASTORE: &str = "micro.nodes.Datastore";
+const MESSAGE_ROUTER: &str = "micro.nodes.MessageRouter";
+const MESSAGE_BROKER: &str = "micro.nodes.MessageBroker";
+const INTERACTS_WITH: &str = "micro.relationships.InteractsWith";
+const EDGE_GROUP: &str = "micro.groups.Edge";
+
+/// The suffixes of the nodes standing for k8s services and ingresses
+const SERVICE_SUFFIX: &str = ".svc";
+const INGRESS_SUFFIX: &str = ".ingress";
+
+/// A microTOSCA topology, as read and written by the microFreshener tools
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MicroTosca {
+    pub tosca_definitions_version: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub imports: Vec<Value>,
+
+    pub topology_template: TopologyTemplate,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TopologyTemplate {
+    #[serde(default)]
+    pub node_templates: BTreeMap<String, NodeTemplate>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub relationship_templates: BTreeMap<String, RelationshipTemplate>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Group>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NodeTemplate {
+    #[serde(rename = "type")]
+    pub node_type: String,
+
+    /// the interactions of the node, each keyed by `interaction`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requirements: Vec<BTreeMap<String, Requirement>>,
+}
+
+/// The target of an interaction, possibly with the relationship template
+/// telling its properties
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Requirement {
+    Node(String),
+    Relationship { node: String, relationship: String },
+}
+
+impl Requirement {
+    pub fn node(&self) -> &str {
+        match self {
+            Requirement::Node(node) => node,
+            Requirement::Relationship { node, .. } => node,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelationshipTemplate {
+    #[serde(rename = "type")]
+    pub relationship_type: String,
+
+    #[serde(default)]
+    pub properties: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Group {
+    #[serde(rename = "type")]
+    pub group_type: String,
+
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+/// The properties of an interaction, which name its relationship template
+/// after their initials (e.g., `tc` for timeout and circuit breaker)
+#[derive(Debug, Clone, Copy, Default)]
+struct Properties {
+    timeout: bool,
+    circuit_breaker: bool,
+    dynamic_discovery: bool,
+}
+
+impl Properties {
+    fn of(template: &RelationshipTemplate) -> Self {
+        let is_set = |property: &str| template.properties.get(property).copied().unwrap_or(false);
+
+        Properties {
+            timeout: is_set("timeout"),
+            circuit_breaker: is_set("circuit_breaker"),
+            dynamic_discovery: is_set("dynamic_discovery"),
+        }
+    }
+
+    fn template_name(&self) -> String {
+        [(self.timeout, 't'), (self.circuit_breaker, 'c'), (self.dynamic_discovery, 'd')]
+            .iter()
+            .filter(|(is_set, _)| *is_set)
+            .map(|(_, initial)| initial)
+            .collect()
+    }
+
+    fn template(&self) -> RelationshipTemplate {
+        let properties = [
+            ("timeout", self.timeout),
+            ("circuit_breaker", self.circuit_breaker),
+            ("dynamic_discovery", self.dynamic_discovery),
+        ];
+
+        RelationshipTemplate {
+            relationship_type: INTERACTS_WITH.to_owned(),
+            properties: properties
+                .iter()
+                .filter(|(_, is_set)| *is_set)
+                .map(|(name, _)| (name.to_string(), true))
+                .collect(),
+        }
+    }
+}
+
+impl MicroTosca {
+    /// It models the deployment analyzed in `ctx` as a microTOSCA topology, where
+    /// workloads become services, datastores or message brokers (by their images),
+    /// k8s services and ingresses become message routers, and the interactions
+    /// tell whether they are guarded by timeouts and circuit breakers (as set by
+    /// Istio) and whether they rely on dynamic discovery (through a k8s service)
+    pub fn from_deployment(ctx: &AnalysisContext) -> Self {
+        let mut topology = TopologyTemplate::default();
+        let mut edge: Vec<String> = vec![];
+
+        for workload in ctx.workloads() {
+            let is_router = workload.all_containers()
+                .iter()
+                .any(|c| c.image.as_ref().is_some_and(|image| ctx.is_known_image(image, ImageKind::Mr)));
+
+            let node_type = match graph::kind_of_workload(workload) {
+                NodeKind::Database => DATASTORE,
+                NodeKind::MessageBroker => MESSAGE_BROKER,
+                _ if is_router => MESSAGE_ROUTER,
+                _ => SERVICE,
+            };
+
+            topology.add_node(workload_node(ctx, workload), node_type);
+        }
+
+        for service in ctx.services() {
+            let name = service_node(ctx, service);
+            topology.add_node(name.clone(), MESSAGE_ROUTER);
+
+            // the timeouts and circuit breakers set on the service guard what goes through it
+            let host = Host { name: service.metadata.name.clone(), namespace: ctx.namespace_of(service).to_owned() };
+            for workload in ctx.selected_by(service) {
+                topology.add_interaction(&name, workload_node(ctx, workload), istio_properties(ctx, &host));
+            }
+
+            if service.is_external_service() {
+                edge.push(name);
+            }
+        }
+
+        for ingress in ctx.of_kind(&["Ingress"]) {
+            let name = format!("{}.{}{}", ingress.metadata.name, ctx.namespace_of(ingress), INGRESS_SUFFIX);
+            topology.add_node(name.clone(), MESSAGE_ROUTER);
+
+            for backend in inference::ingress_backends(&ingress.raw) {
+                let host = ctx.host_from(&backend, ingress);
+                let service = ctx.named(&host.name)
+                    .into_iter()
+                    .find(|m| m.kind == "Service" && ctx.namespace_of(m) == host.namespace);
+
+                if let Some(service) = service {
+                    topology.add_interaction(&name, service_node(ctx, service), Properties::default());
+                }
+            }
+
+            edge.push(name);
+        }
+
+        for interaction in inference::interactions(ctx) {
+            let Some(caller) = interaction.caller.and_then(|c| ctx.workload_for(&c)) else { continue };
+            let Some(callee) = ctx.workload_for(&interaction.callee) else { continue };
+
+            let properties = Properties {
+                dynamic_discovery: !ctx.services_selecting(callee).is_empty(),
+                ..istio_properties(ctx, &interaction.callee)
+            };
+
+            topology.add_interaction(&workload_node(ctx, caller), workload_node(ctx, callee), properties);
+        }
+
+        if !edge.is_empty() {
+            topology.groups.insert(
+                String::from("edgenodes"),
+                Group { group_type: EDGE_GROUP.to_owned(), members: edge }
+            );
+        }
+
+        let mut import = serde_yaml::Mapping::new();
+        import.insert(Value::from("micro"), Value::from(MICRO_TOSCA_URI));
+
+        MicroTosca {
+            tosca_definitions_version: TOSCA_VERSION.to_owned(),
+            description: Some(String::from("Generated by KubeFreshener from the k8s manifests")),
+            imports: vec![Value::Mapping(import)],
+            topology_template: topology,
+        }
+    }
+
+    /// It updates the config according to the topology: the services (and the
+    /// datastores and message brokers) that other nodes interact with become the
+    /// invoked services, those whose interactions all have a timeout or a circuit
+    /// breaker are not checked for wobbly interactions, those whose interactions
+    /// all rely on dynamic discovery (or go through message routers) are not checked
+    /// for endpoint based interactions, and message routers are not checked for
+    /// missing API gateways, in addition to what the config already ignores
+    pub fn update_config(&self, config: &mut Config) {
+        let nodes = &self.topology_template.node_templates;
+        let templates = &self.topology_template.relationship_templates;
+        let type_of = |name: &str| nodes.get(name).map(|n| n.node_type.as_str());
+
+        // the interactions reaching each node, with the node they come from
+        let mut sources: BTreeMap<&str, Vec<(&str, Properties)>> = BTreeMap::new();
+
+        for (name, node) in nodes {
+            for requirement in node.requirements.iter().filter_map(|r| r.get("interaction")) {
+                let properties = match requirement {
+                    Requirement::Relationship { relationship, .. } =>
+                        templates.get(relationship).map(Properties::of).unwrap_or_default(),
+                    Requirement::Node(_) => Properties::default(),
+                };
+
+                sources.entry(requirement.node()).or_default().push((name.as_str(), properties));
+            }
+        }
+
+        // the interactions reaching each service (or datastore, or message broker),
+        // where those from message routers are followed back to the nodes
+        // interacting with the routers
+        let incoming: BTreeMap<&str, Vec<Properties>> = sources
+            .iter()
+            .filter(|(target, _)| matches!(type_of(target), Some(SERVICE | DATASTORE | MESSAGE_BROKER)))
+            .map(|(target, reaching)| {
+                let properties = reaching
+                    .iter()
+                    .flat_map(|(source, properties)| match type_of(source) == Some(MESSAGE_ROUTER) {
+                        true => routed(&sources, &type_of, source, *properties, &mut vec![]),
+                        false => vec![*properties],
+                    })
+                    .collect();
+
+                (*target, properties)
+            })
+            .collect();
+
+        let names = |is_ignored: fn(&Properties) -> bool| -> Vec<String> {
+            incoming
+                .iter()
+                .filter(|(_, properties)| !properties.is_empty() && properties.iter().all(is_ignored))
+                .map(|(name, _)| name.to_string())
+                .collect()
+        };
+
+        // the smells already ignored in the config stay ignored
+        let merge = |ignored: &mut Vec<String>, names: Vec<String>| {
+            for name in names {
+                if !ignored.contains(&name) {
+                    ignored.push(name);
+                }
+            }
+        };
+
+        config.invoked_services = Some(incoming.keys().map(|name| name.to_string()).collect());
+        merge(&mut config.ignore_smells.wobbly, names(|p| p.timeout || p.circuit_breaker));
+        merge(&mut config.ignore_smells.endpoint_based_interaction, names(|p| p.dynamic_discovery));
+
+        // the routers standing for k8s services and ingresses are not workloads
+        let routers = nodes
+            .iter()
+            .filter(|(_, node)| node.node_type == MESSAGE_ROUTER)
+            .filter(|(name, _)| !name.ends_with(SERVICE_SUFFIX) && !name.ends_with(INGRESS_SUFFIX))
+            .map(|(name, _)| Host::resolve(name, config.default_namespace()).name);
+
+        for name in routers.collect::<Vec<String>>() {
+            if !config.ignore_smells.noapigateway.iter().any(|n| n.name == name) {
+                config.ignore_smells.noapigateway.push(NodeConfigElement { name, containers: None });
+            }
+        }
+    }
+}
+
+/// It returns the properties of the interactions reaching a node through
+/// `router`, each combined with those of the interaction from the router
+/// (`through`). Interactions through message routers rely on dynamic discovery,
+/// and those entering the topology at the router count as a single one.
+fn routed<'a>(
+    sources: &BTreeMap<&'a str, Vec<(&'a str, Properties)>>,
+    type_of: &dyn Fn(&str) -> Option<&'a str>,
+    router: &'a str,
+    through: Properties,
+    visited: &mut Vec<&'a str>
+) -> Vec<Properties> {
+    visited.push(router);
+
+    let combined = |properties: Properties| Properties {
+        timeout: properties.timeout || through.timeout,
+        circuit_breaker: properties.circuit_breaker || through.circuit_breaker,
+        dynamic_discovery: true,
+    };
+
+    let mut reaching = vec![];
+    for (source, properties) in sources.get(router).into_iter().flatten() {
+        if visited.contains(source) { continue }
+
+        match type_of(source) == Some(MESSAGE_ROUTER) {
+            true => reaching.extend(routed(sources, type_of, source, combined(*properties), visited)),
+            false => reaching.push(combined(*properties)),
+        }
+    }
+
+    if reaching.is_empty() {
+        reaching.push(combined(Properties::default()));
+    }
+
+    reaching
+}
+
+impl TopologyTemplate {
+    fn add_node(&mut self, name: String, node_type: &str) {
+        self.node_templates.insert(name, NodeTemplate { node_type: node_type.to_owned(), requirements: vec![] });
+    }
+
+    fn add_interaction(&mut self, source: &str, target: String, properties: Properties) {
+        let relationship = properties.template_name();
+
+        let requirement = match relationship.is_empty() {
+            true => Requirement::Node(target),
+            false => {
+                self.relationship_templates
+                    .entry(relationship.clone())
+                    .or_insert_with(|| properties.template());
+                Requirement::Relationship { node: target, relationship }
+            },
+        };
+
+        if let Some(node) = self.node_templates.get_mut(source) {
+            let mut interaction = BTreeMap::new();
+            interaction.insert(String::from("interaction"), requirement);
+            node.requirements.push(interaction);
+        }
+    }
+}
+
+/// It returns the properties of the interactions with `host`, as set by the
+/// Istio VirtualServices (timeouts) and DestinationRules (circuit breakers) of it
+fn istio_properties(ctx: &AnalysisContext, host: &Host) -> Properties {
+    Properties {
+        timeout: ctx.virtual_services_for(host)
+            .iter()
+            .flat_map(|vs| vs.spec.http.iter().flatten())
+            .any(|http| http.timeout.is_some()),
+        circuit_breaker: ctx.has_circuit_breaker(host),
+        dynamic_discovery: false,
+    }
+}
+
+/// It names the node of a workload as its invoked service would be named
+/// in the config, i.e., qualified by its namespace unless it's the default one
+fn workload_node(ctx: &AnalysisContext, workload: &K8SManifest) -> String {
+    let namespace = ctx.namespace_of(workload);
+
+    match namespace == ctx.config.default_namespace() {
+        true => workload.metadata.name.clone(),
+        false => format!("{}.{}", workload.metadata.name, namespace),
+    }
+}
+
+/// It names the node of a k8s service after its DNS name, which sets it
+/// apart from the workloads it selects
+fn service_node(ctx: &AnalysisContext, service: &K8SManifest) -> String {
+    format!("{}.{}{}", service.metadata.name, ctx.namespace_of(service), SERVICE_SUFFIX)
+}
@@ -0,0 +1,181 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::report::{Finding, Report, Smell};
+
+const TOOL_NAME: &str = "KubeFreshener";
+const TOOL_URI: &str = "https://github.com/di-unipi-socc/kube-freshener";
+
+/// The formats an analysis report can be rendered to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Sarif,
+    Junit,
+}
+
+/// It prints the report on stdout in the given format
+pub fn emit(report: &Report, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => report.print(),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(report).unwrap()),
+        OutputFormat::Sarif => println!("{}", serde_json::to_string_pretty(&to_sarif(report)).unwrap()),
+        OutputFormat::Junit => print!("{}", to_junit(report)),
+    }
+}
+
+/// It converts the report to a SARIF 2.1.0 log, with one rule per smell
+fn to_sarif(report: &Report) -> Value {
+    let smells = Smell::all();
+
+    let rules: Vec<Value> = smells
+        .iter()
+        .map(|smell| json!({
+            "id": smell.id(),
+            "name": smell.title().replace(' ', ""),
+            "shortDescription": { "text": smell.title() },
+        }))
+        .collect();
+
+    let results: Vec<Value> = report.findings
+        .iter()
+        .map(|finding| {
+            let mut text = finding.message.clone();
+            if let Some(hint) = &finding.hint {
+                text.push_str(&format!(" Hint: {}", hint));
+            }
+
+            let mut result = json!({
+                "ruleId": finding.smell.id(),
+                "ruleIndex": smells.iter().position(|s| *s == finding.smell),
+                "level": finding.severity.to_str(),
+                "message": { "text": text },
+            });
+
+            if let Some(file) = &finding.file {
+                result["locations"] = json!([{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": to_uri(file) },
+                        "region": { "startLine": finding.line.unwrap_or(1) },
+                    }
+                }]);
+            }
+
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": TOOL_NAME,
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": TOOL_URI,
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+/// It turns a file path into a relative URI, as expected by SARIF viewers
+fn to_uri(file: &str) -> String {
+    let uri = file.replace('\\', "/");
+
+    match uri.strip_prefix("./") {
+        Some(relative) => relative.to_owned(),
+        None => uri,
+    }
+}
+
+/// It converts the report to JUnit XML: each smell is a test suite and each
+/// of its occurrences is a failing test case
+fn to_junit(report: &Report) -> String {
+    let mut suites = String::new();
+
+    for smell in Smell::all() {
+        let findings: Vec<&Finding> = report.findings
+            .iter()
+            .filter(|f| f.smell == smell)
+            .collect();
+
+        suites.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+            escape_xml(smell.title()),
+            findings.len().max(1),
+            findings.len(),
+        ));
+
+        if findings.is_empty() {
+            suites.push_str(&format!(
+                "    <testcase classname=\"{}\" name=\"no occurrences\"/>\n",
+                smell.id()
+            ));
+        }
+
+        for finding in findings {
+            let mut details = finding.message.clone();
+            if let Some(hint) = &finding.hint {
+                details.push_str(&format!("\nHint: {}", hint));
+            }
+            if let Some(file) = &finding.file {
+                details.push_str(&format!("\nAt: {}:{}", file, finding.line.unwrap_or(1)));
+            }
+
+            suites.push_str(&format!(
+                "    <testcase classname=\"{}\" name=\"{}\">\n      \
+                <failure message=\"{}\" type=\"{}\">{}</failure>\n    \
+                </testcase>\n",
+                smell.id(),
+                escape_xml(&testcase_name(finding)),
+                escape_xml(&finding.message),
+                finding.severity.to_str(),
+                escape_xml(&details),
+            ));
+        }
+
+        suites.push_str("  </testsuite>\n");
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <testsuites name=\"{}\" tests=\"{}\" failures=\"{}\">\n{}</testsuites>\n",
+        TOOL_NAME,
+        Smell::all()
+            .iter()
+            .map(|s| report.findings.iter().filter(|f| f.smell == *s).count().max(1))
+            .sum::<usize>(),
+        report.findings.len(),
+        suites,
+    )
+}
+
+fn testcase_name(finding: &Finding) -> String {
+    let mut name = match (&finding.manifest, &finding.service) {
+        (Some(manifest), _) => match &manifest.namespace {
+            Some(namespace) => format!("{}/{}/{}", manifest.kind, namespace, manifest.name),
+            None => format!("{}/{}", manifest.kind, manifest.name),
+        },
+        (None, Some(service)) => service.clone(),
+        (None, None) => finding.smell.id().to_owned(),
+    };
+
+    if let Some(container) = &finding.container {
+        name.push_str(&format!(" ({})", container));
+    }
+
+    name
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
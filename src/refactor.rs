@@ -0,0 +1,484 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::context::AnalysisContext;
+use crate::error::Error;
+use crate::k8s_types::*;
+use crate::inference;
+use crate::report::{Finding, Fix, ManifestRef, Report};
+use crate::yaml_handler;
+use serde_yaml::{Mapping, Value};
+use similar::TextDiff;
+use walkdir::WalkDir;
+
+/// A change the refactorings make to a file
+pub struct FileChange {
+    pub path: PathBuf,
+    /// the content of the file before the refactorings, if it already exists
+    pub original: Option<String>,
+    pub updated: String,
+    /// whether the file declares resources generated by the refactorings
+    pub generated: bool,
+}
+
+/// A manifest updated by the refactorings
+pub struct ManifestChange {
+    pub original: K8SManifest,
+    /// the YAML tree of the manifest, once refactored
+    pub updated: Value,
+}
+
+/// The changes to the files computed by the refactorings, which can be
+/// either applied to disk or rendered as a patch
+#[derive(Default)]
+pub struct ChangeSet {
+    pub files: Vec<FileChange>,
+    pub manifests: Vec<ManifestChange>,
+    /// the number of manifests left as they are, as their files changed since the analysis
+    pub skipped: usize,
+}
+
+impl ChangeSet {
+    /// It returns the content of a file, with the changes recorded so far
+    fn content_of(&self, path: &Path) -> Result<String, Error> {
+        match self.files.iter().find(|c| c.path == path) {
+            Some(change) => Ok(change.updated.clone()),
+            None => fs::read_to_string(path).map_err(|e| Error::io(path, e)),
+        }
+    }
+
+    /// It records the new content of a file
+    fn write(&mut self, path: PathBuf, updated: String) -> Result<(), Error> {
+        self.record(path, updated, false)
+    }
+
+    /// It records a file declaring resources generated by the refactorings. The
+    /// file never replaces another one: if `path` exists or it is already recorded,
+    /// the file is numbered (e.g., `web-srv-1.yaml`), unless the very same content
+    /// has already been generated there.
+    fn create(&mut self, path: PathBuf, content: String) -> Result<(), Error> {
+        let is_taken = |path: &Path| match self.files.iter().find(|c| c.path == path) {
+            Some(change) => !change.generated || change.updated != content,
+            None => path.exists(),
+        };
+
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let mut unique = path.clone();
+        for i in 1.. {
+            if !is_taken(&unique) { break }
+            unique = path.with_file_name(format!("{}-{}.yaml", stem, i));
+        }
+
+        self.record(unique, content, true)
+    }
+
+    /// It records the content of a file, which cannot be both generated by the
+    /// refactorings and a manifest they update
+    fn record(&mut self, path: PathBuf, updated: String, generated: bool) -> Result<(), Error> {
+        match self.files.iter_mut().find(|c| c.path == path) {
+            Some(change) if change.generated != generated => {
+                let source = io::Error::new(io::ErrorKind::AlreadyExists, "a refactoring would overwrite the file");
+                return Err(Error::io(&path, source));
+            },
+            Some(change) => change.updated = updated,
+            None => {
+                let original = fs::read_to_string(&path).ok();
+                self.files.push(FileChange { path, original, updated, generated });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// It returns the YAML tree of a manifest, with the fixes applied so far
+    fn updated_of(&mut self, original: &K8SManifest) -> &mut Value {
+        let is_same = |m: &K8SManifest| match (&m.source, &original.source) {
+            (Some(a), Some(b)) => a.path == b.path && a.document == b.document,
+            _ => false,
+        };
+
+        let position = match self.manifests.iter().position(|c| is_same(&c.original)) {
+            Some(position) => position,
+            None => {
+                self.manifests.push(ManifestChange {
+                    original: original.clone(),
+                    updated: original.raw.clone(),
+                });
+                self.manifests.len() - 1
+            }
+        };
+
+        &mut self.manifests[position].updated
+    }
+
+    /// It writes the changed files to disk
+    pub fn apply(&self) -> Result<(), Error> {
+        for change in &self.files {
+            fs::write(&change.path, &change.updated).map_err(|e| Error::io(&change.path, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// It writes the whole deployment to the fresh folder `out_dir`, with the
+    /// changes applied, leaving the manifests folders untouched. If there are
+    /// many manifests folders, each of them is mirrored in a subfolder of its own.
+    pub fn apply_to_dir(&self, manifest_dirs: &[PathBuf], out_dir: &Path) -> Result<(), Error> {
+        let is_fresh = fs::read_dir(out_dir).map_or(true, |mut entries| entries.next().is_none());
+        if !is_fresh {
+            let source = io::Error::new(io::ErrorKind::AlreadyExists, "the output folder is not empty");
+            return Err(Error::io(out_dir, source));
+        }
+
+        let mirrored = |path: &Path| -> Option<PathBuf> {
+            manifest_dirs.iter().find_map(|dir| {
+                let relative = path.strip_prefix(dir).ok()?;
+
+                match (manifest_dirs.len(), dir.file_name()) {
+                    (1, _) | (_, None) => Some(out_dir.join(relative)),
+                    (_, Some(name)) => Some(out_dir.join(name).join(relative)),
+                }
+            })
+        };
+
+        // the files left as they are...
+        let unchanged: Vec<PathBuf> = manifest_dirs
+            .iter()
+            .flat_map(|dir| WalkDir::new(dir).follow_links(true))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.path().to_path_buf())
+            .filter(|path| !path.starts_with(out_dir) && self.files.iter().all(|c| c.path != *path))
+            .collect();
+
+        for path in unchanged {
+            let Some(target) = mirrored(&path) else { continue };
+            create_parent(&target)?;
+            fs::copy(&path, &target).map_err(|e| Error::io(&target, e))?;
+        }
+
+        // ...and the changed ones
+        for change in &self.files {
+            let Some(target) = mirrored(&change.path) else { continue };
+            create_parent(&target)?;
+            fs::write(&target, &change.updated).map_err(|e| Error::io(&target, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// It renders the changes as a unified diff, which can be applied with `git apply`
+    pub fn to_patch(&self) -> String {
+        let mut patch = String::new();
+
+        for change in &self.files {
+            let original = change.original.as_deref().unwrap_or_default();
+            if change.original.is_some() && original == change.updated {
+                continue;
+            }
+
+            let path = patch_path(&change.path);
+            patch.push_str(&format!("diff --git a/{0} b/{0}\n", path));
+
+            let old = match change.original {
+                Some(_) => format!("a/{}", path),
+                None => {
+                    patch.push_str("new file mode 100644\n");
+                    String::from("/dev/null")
+                }
+            };
+
+            patch.push_str(
+                &TextDiff::from_lines(original, &change.updated)
+                    .unified_diff()
+                    .header(&old, &format!("b/{}", path))
+                    .to_string()
+            );
+        }
+
+        patch
+    }
+
+    pub fn save_patch(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, self.to_patch()).map_err(|e| Error::io(path, e))
+    }
+}
+
+fn create_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(|e| Error::io(parent, e)),
+        None => Ok(()),
+    }
+}
+
+/// It turns a file path into the relative path expected in patches, i.e.,
+/// relative to the current directory (where the patch is to be applied) or,
+/// for the absolute paths out of it, to the root directory
+fn patch_path(path: &Path) -> String {
+    let relative = match std::env::current_dir() {
+        Ok(current) if path.is_absolute() => path
+            .strip_prefix(&current)
+            .ok()
+            .map(Path::to_path_buf)
+            .or_else(|| {
+                let canonical = current.canonicalize().ok()?;
+                let path = path.parent()?.canonicalize().ok()?.join(path.file_name()?);
+                path.strip_prefix(&canonical).ok().map(Path::to_path_buf)
+            })
+            .unwrap_or_else(|| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    };
+
+    relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            Component::ParentDir => Some("..".into()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// It computes the changes of all the fixes proposed in the report: fixes on
+/// the same manifest are applied together, so that each manifest is rewritten once
+pub fn plan(ctx: &AnalysisContext, report: &Report) -> Result<ChangeSet, Error> {
+    let mut changes = ChangeSet::default();
+
+    // containers are extracted last, so that they are moved to their own pod
+    // with the fixes of the other findings on them (e.g., host ports removed)
+    let mut fixes: Vec<(&Finding, &Fix)> = report.findings
+        .iter()
+        .flat_map(|finding| finding.fixes.iter().map(move |fix| (finding, fix)))
+        .collect();
+    fixes.sort_by_key(|(_, fix)| matches!(fix, Fix::ExtractContainer { .. }));
+
+    for (finding, fix) in fixes {
+        match fix {
+            Fix::CreateService { name, namespace, selector } => {
+                let (path, yaml) = yaml_handler::service_for(
+                    ctx.output_dir(),
+                    name.clone(),
+                    namespace.clone(),
+                    selector
+                )?;
+                changes.create(path, yaml)?;
+            },
+            Fix::CreateVirtualService { host, namespace } => {
+                let (path, yaml) = yaml_handler::virtual_service_for(
+                    ctx.output_dir(),
+                    host.clone(),
+                    namespace.clone()
+                )?;
+                changes.create(path, yaml)?;
+            },
+            Fix::CreateDatastore { name, namespace, image, port, consumer } => {
+                let (path, yaml) = yaml_handler::datastore_for(
+                    ctx.output_dir(),
+                    name.clone(),
+                    namespace.clone(),
+                    image.clone(),
+                    *port
+                )?;
+                changes.create(path, yaml)?;
+
+                // the consumer reaches its own datastore, instead of the one of the finding
+                let Some(datastore) = &finding.manifest else { continue };
+                let Some(consumer) = ctx.manifests.iter().find(|m| ManifestRef::of(m) == *consumer) else { continue };
+
+                let shared = Host {
+                    name: datastore.name.clone(),
+                    namespace: datastore.namespace.clone().unwrap_or_else(|| ctx.config.default_namespace().to_owned()),
+                };
+                let consumer_namespace = ctx.namespace_of(consumer);
+                for_each_env_var(changes.updated_of(consumer), |var, value| {
+                    inference::redirected(ctx, var, value, consumer_namespace, &shared, name)
+                });
+            },
+            Fix::CreateVolumeClaim { name, namespace, claim, consumer } => {
+                // the new claim is alike the shared one, in the same namespace
+                let claim_namespace = namespace.as_deref().unwrap_or(ctx.config.default_namespace());
+                let like = ctx.named(claim)
+                    .into_iter()
+                    .find(|m| m.kind == "PersistentVolumeClaim" && ctx.namespace_of(m) == claim_namespace);
+
+                let (path, yaml) = yaml_handler::volume_claim_for(
+                    ctx.output_dir(),
+                    name.clone(),
+                    namespace.clone(),
+                    like
+                )?;
+                changes.create(path, yaml)?;
+
+                // the consumer mounts its own claim, instead of the shared one
+                let Some(consumer) = ctx.manifests.iter().find(|m| ManifestRef::of(m) == *consumer) else { continue };
+                for_each_pod_spec(changes.updated_of(consumer), |spec| {
+                    let Some(Value::Sequence(volumes)) = spec.get_mut("volumes") else { return };
+
+                    for volume in volumes {
+                        let Some(source) = volume.get_mut("persistentVolumeClaim") else { continue };
+                        if source.get("claimName").and_then(|c| c.as_str()) == Some(claim) {
+                            source["claimName"] = Value::from(name.as_str());
+                        }
+                    }
+                });
+            },
+            _ => {
+                // the manifest to refactor is the one the finding has been read from
+                let (Some(file), Some(document)) = (&finding.file, finding.document) else { continue };
+                let Some(original) = ctx.declared_at(file, document) else { continue };
+
+                let generated = apply_to(ctx, changes.updated_of(original), fix)?;
+                if let Some((path, yaml)) = generated {
+                    changes.create(path, yaml)?;
+                }
+            }
+        }
+    }
+
+    // each manifest is written back exactly where it has been read from
+    for i in 0..changes.manifests.len() {
+        let original = &changes.manifests[i].original;
+        let Some(source) = &original.source else { continue };
+
+        let path = source.path.clone();
+        let content = changes.content_of(&path)?;
+
+        let change = &changes.manifests[i];
+        match yaml_handler::update_document(&path, &content, &change.original, &change.updated)? {
+            Some(rewritten) => changes.write(path, rewritten)?,
+            None => {
+                eprintln!(
+                    "[X] {} {} has been changed in {} since its analysis, hence it is not refactored",
+                    change.original.kind,
+                    change.original.metadata.name,
+                    path.display()
+                );
+                changes.skipped += 1;
+            },
+        }
+    }
+
+    Ok(changes)
+}
+
+/// It applies a fix to the YAML tree of a manifest, so that whatever
+/// the fix is not about is preserved as it is. It returns the file
+/// of the resource generated by the fix, if any.
+fn apply_to(
+    ctx: &AnalysisContext,
+    raw: &mut Value,
+    fix: &Fix
+) -> Result<Option<(PathBuf, String)>, Error> {
+    match fix {
+        Fix::ExtractContainer { container } => {
+            // the container is removed from the current pod/deployment...
+            let mut extracted: Option<Value> = None;
+            for_each_pod_spec(raw, |spec| {
+                if let Some(Value::Sequence(containers)) = spec.get_mut("containers") {
+                    if let Some(i) = containers.iter().position(|c| has_name(c, &container.name)) {
+                        extracted = Some(containers.remove(i));
+                    }
+                }
+            });
+
+            // ...and moved to a pod of its own, in the same namespace
+            let namespace = raw.get("metadata")
+                .and_then(|m| m.get("namespace"))
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_owned());
+
+            let extracted = match extracted {
+                Some(extracted) => extracted,
+                None => serde_yaml::to_value(container)
+                    .map_err(|e| Error::serialize(ctx.output_dir(), e))?,
+            };
+            return yaml_handler::pod_from(ctx.output_dir(), &extracted, namespace).map(Some);
+        },
+        Fix::RemoveHostNetwork => for_each_pod_spec(raw, |spec| {
+            spec.shift_remove("hostNetwork");
+        }),
+        Fix::RemoveHostPorts { container } => for_each_pod_spec(raw, |spec| {
+            let Some(Value::Sequence(containers)) = spec.get_mut("containers") else { return };
+
+            for c in containers.iter_mut().filter(|c| has_name(c, container)) {
+                let Some(Value::Sequence(ports)) = c.get_mut("ports") else { continue };
+
+                for port in ports.iter_mut().filter_map(|p| p.as_mapping_mut()) {
+                    port.shift_remove("hostPort");
+                }
+            }
+        }),
+        Fix::ReplaceEnvValue { container, name, value } => for_each_pod_spec(raw, |spec| {
+            let Some(Value::Sequence(containers)) = spec.get_mut("containers") else { return };
+
+            for c in containers.iter_mut().filter(|c| has_name(c, container)) {
+                let Some(Value::Sequence(env)) = c.get_mut("env") else { continue };
+
+                for var in env.iter_mut().filter(|v| has_name(v, name)) {
+                    var["value"] = Value::from(value.as_str());
+                }
+            }
+        }),
+        Fix::ReplaceConfigValue { key, value } => {
+            if let Some(Value::Mapping(data)) = raw.get_mut("data") {
+                data.insert(Value::from(key.as_str()), Value::from(value.as_str()));
+            }
+        },
+        Fix::CreateService { .. }
+        | Fix::CreateVirtualService { .. }
+        | Fix::CreateDatastore { .. }
+        | Fix::CreateVolumeClaim { .. } => {}
+    }
+
+    Ok(None)
+}
+
+/// It calls `f` on the pod spec of a pod and on the one of a workload's template,
+/// including the template nested in the job template of a cron job
+fn for_each_pod_spec(raw: &mut Value, mut f: impl FnMut(&mut Mapping)) {
+    let Some(spec) = raw.get_mut("spec").and_then(|s| s.as_mapping_mut()) else { return };
+    f(spec);
+
+    let spec = match spec.contains_key("jobTemplate") {
+        true => spec.get_mut("jobTemplate")
+            .and_then(|t| t.get_mut("spec"))
+            .and_then(|s| s.as_mapping_mut()),
+        false => Some(spec),
+    };
+    let Some(spec) = spec else { return };
+
+    let template_spec = spec.get_mut("template")
+        .and_then(|t| t.get_mut("spec"))
+        .and_then(|s| s.as_mapping_mut());
+
+    if let Some(template_spec) = template_spec {
+        f(template_spec);
+    }
+}
+
+/// It sets the value of each env var of the containers of a pod (or of a workload's
+/// template) to the one returned by `f` given its name and its value, if any
+fn for_each_env_var(raw: &mut Value, mut f: impl FnMut(&str, &str) -> Option<String>) {
+    for_each_pod_spec(raw, |spec| {
+        let Some(Value::Sequence(containers)) = spec.get_mut("containers") else { return };
+
+        for container in containers {
+            let Some(Value::Sequence(env)) = container.get_mut("env") else { continue };
+
+            for var in env {
+                let name = var.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+                let Some(value) = var.get("value").and_then(|v| v.as_str()) else { continue };
+
+                if let Some(value) = f(name, value) {
+                    var["value"] = Value::from(value);
+                }
+            }
+        }
+    });
+}
+
+fn has_name(object: &Value, name: &str) -> bool {
+    object.get("name").and_then(|n| n.as_str()) == Some(name)
+}
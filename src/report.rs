@@ -0,0 +1,294 @@
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::k8s_types::*;
+
+/// The architectural smells KubeFreshener is able to detect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Smell {
+    MultipleContainer,
+    NoApiGateway,
+    EndpointBasedInteraction,
+    WobblyInteraction,
+    UnmatchedTargetPort,
+    SharedPersistence,
+    HardcodedEndpoint,
+}
+
+impl Smell {
+    pub fn all() -> Vec<Smell> {
+        vec![
+            Self::MultipleContainer,
+            Self::NoApiGateway,
+            Self::EndpointBasedInteraction,
+            Self::WobblyInteraction,
+            Self::UnmatchedTargetPort,
+            Self::SharedPersistence,
+            Self::HardcodedEndpoint,
+        ]
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::all().into_iter().find(|s| s.id() == id)
+    }
+
+    /// It returns the identifier of the smell, as used in machine readable outputs
+    pub fn id(&self) -> &str {
+        match self {
+            Self::MultipleContainer => "multiple_container",
+            Self::NoApiGateway => "no_api_gateway",
+            Self::EndpointBasedInteraction => "endpoint_based_interaction",
+            Self::WobblyInteraction => "wobbly_interaction",
+            Self::UnmatchedTargetPort => "unmatched_target_port",
+            Self::SharedPersistence => "shared_persistence",
+            Self::HardcodedEndpoint => "hardcoded_endpoint",
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        match self {
+            Self::MultipleContainer => "Multiple containers per Deployment",
+            Self::NoApiGateway => "No API Gateway",
+            Self::EndpointBasedInteraction => "Endpoint Based Interaction",
+            Self::WobblyInteraction => "Wobbly Interaction",
+            Self::UnmatchedTargetPort => "Unmatched Service Target Port",
+            Self::SharedPersistence => "Shared Persistence",
+            Self::HardcodedEndpoint => "Hardcoded Endpoint",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "warning" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            _ => None
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// It selects the findings that make an analysis fail: either all the
+/// occurrences of a smell or all the findings at least as severe as a severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailOn {
+    Smell(Smell),
+    Severity(Severity),
+}
+
+impl FailOn {
+    pub fn from_str(s: &str) -> Option<Self> {
+        Smell::from_id(s)
+            .map(Self::Smell)
+            .or_else(|| Severity::from_str(s).map(Self::Severity))
+    }
+
+    pub fn names() -> Vec<String> {
+        Smell::all()
+            .iter()
+            .map(|s| s.id())
+            .chain([Severity::Warning.to_str(), Severity::Error.to_str()])
+            .map(|k| k.to_owned())
+            .collect()
+    }
+
+    pub fn matches(&self, finding: &Finding) -> bool {
+        match self {
+            Self::Smell(smell) => finding.smell == *smell,
+            Self::Severity(severity) => finding.severity >= *severity,
+        }
+    }
+}
+
+/// A refactoring that resolves (part of) a smell occurrence.
+/// Fixes touching an existing manifest apply to the manifest of their finding.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Fix {
+    /// move the container to a brand new pod and drop it from the manifest
+    ExtractContainer { container: Box<Container> },
+    RemoveHostNetwork,
+    RemoveHostPorts { container: String },
+    /// set the value of an env var of a container
+    ReplaceEnvValue { container: String, name: String, value: String },
+    /// set the value of an entry of a ConfigMap
+    ReplaceConfigValue { key: String, value: String },
+    CreateService {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+        selector: Labels,
+    },
+    CreateVirtualService {
+        host: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+    },
+    /// scaffold a datastore of its own (i.e., a deployment and its service) for a consumer,
+    /// and point the env vars of the consumer reaching the shared datastore to it
+    CreateDatastore {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+        image: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        port: Option<i32>,
+        consumer: ManifestRef,
+    },
+    /// scaffold a persistent volume claim of its own for a consumer, like the shared `claim`,
+    /// and point the volumes of the consumer mounting the shared claim to it
+    CreateVolumeClaim {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        namespace: Option<String>,
+        claim: String,
+        consumer: ManifestRef,
+    },
+}
+
+/// It identifies the manifest a finding is about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestRef {
+    pub kind: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl ManifestRef {
+    pub fn of(manifest: &K8SManifest) -> Self {
+        ManifestRef {
+            kind: manifest.kind.clone(),
+            name: manifest.metadata.name.clone(),
+            namespace: manifest.metadata.namespace.clone(),
+        }
+    }
+}
+
+/// A single occurrence of a smell
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub smell: Smell,
+    pub severity: Severity,
+    pub manifest: Option<ManifestRef>,
+    pub file: Option<String>,
+    pub document: Option<usize>,
+    pub line: Option<usize>,
+    pub container: Option<String>,
+    pub service: Option<String>,
+    pub message: String,
+    pub hint: Option<String>,
+    pub fixes: Vec<Fix>,
+}
+
+impl Finding {
+    pub fn new(smell: Smell, severity: Severity, message: String) -> Self {
+        Finding {
+            smell,
+            severity,
+            manifest: None,
+            file: None,
+            document: None,
+            line: None,
+            container: None,
+            service: None,
+            message,
+            hint: None,
+            fixes: vec![],
+        }
+    }
+
+    pub fn on_manifest(mut self, manifest: &K8SManifest) -> Self {
+        self.manifest = Some(ManifestRef::of(manifest));
+        self.file = manifest.source
+            .as_ref()
+            .map(|s| s.path.display().to_string());
+        self.document = manifest.source.as_ref().map(|s| s.document);
+        self.line = manifest.source.as_ref().map(|s| s.line);
+        self
+    }
+
+    pub fn with_container(mut self, container: &str) -> Self {
+        self.container = Some(container.to_owned());
+        self
+    }
+
+    pub fn with_service(mut self, service: &str) -> Self {
+        self.service = Some(service.to_owned());
+        self
+    }
+
+    pub fn with_hint(mut self, hint: &str) -> Self {
+        self.hint = Some(hint.to_owned());
+        self
+    }
+
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fixes.push(fix);
+        self
+    }
+}
+
+/// The outcome of an analysis, i.e., all the findings of all the checks
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn extend(&mut self, findings: Vec<Finding>) {
+        self.findings.extend(findings);
+    }
+
+    /// It tells whether any finding matches the given thresholds,
+    /// where no threshold at all means that any finding does
+    pub fn fails_on(&self, thresholds: &[FailOn]) -> bool {
+        if thresholds.is_empty() {
+            return !self.findings.is_empty();
+        }
+
+        self.findings
+            .iter()
+            .any(|f| thresholds.iter().any(|t| t.matches(f)))
+    }
+
+    /// It prints the findings in a human readable way
+    pub fn print(&self) {
+        for finding in &self.findings {
+            let location = match &finding.manifest {
+                Some(ManifestRef { name, namespace: Some(namespace), .. }) =>
+                    format!(" => in {} (namespace {})", name, namespace),
+                Some(manifest) => format!(" => in {}", manifest.name),
+                None => String::new(),
+            };
+
+            println!(
+                "{}{}\n(*) {}",
+                format!("! [{}]", finding.smell.title()).red().bold(),
+                location.yellow().bold(),
+                finding.message,
+            );
+
+            if let Some(hint) = &finding.hint {
+                println!("{} {}", "Hint:".yellow().italic(), hint);
+            }
+
+            println!();
+        }
+    }
+}
@@ -0,0 +1,562 @@
+use std::cmp::Reverse;
+
+use serde_yaml::Value;
+
+/// A step of the path going from the root of a YAML document to one of its nodes
+#[derive(Debug, Clone)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// A node of a YAML document laid out in block style, i.e., the lines
+/// [first, end) of the document, with its keys (or dashes) at `column`
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    first: usize,
+    end: usize,
+    column: usize,
+}
+
+/// A change turning a YAML document into its updated version
+#[derive(Debug, Clone)]
+pub enum Edit {
+    /// the node at the path is removed
+    Remove(Vec<Step>),
+    /// the scalar at the path is replaced with the given one
+    Replace(Vec<Step>, Value),
+    /// the given scalar is appended to the sequence at the path
+    Append(Vec<Step>, Value),
+}
+
+/// The deletion of the lines [start, end) of a document. If the deleted lines
+/// start with the dash of a sequence item, the dash is moved to line `end`.
+#[derive(Debug)]
+struct Deletion {
+    start: usize,
+    end: usize,
+    moved_dash: Option<String>,
+}
+
+/// It rewrites the text of a YAML document declaring `original` so that it
+/// declares `updated`, by only deleting the lines of the keys and items that
+/// `updated` lacks, by rewriting the lines of the scalars it replaces and by
+/// adding those of the scalars it appends to sequences: comments, key order and
+/// quoting of all the rest are kept. It returns `None` when `updated` is not
+/// obtained from `original` by such edits only, or when the text has a layout
+/// the editor cannot follow (e.g., flow style).
+pub fn rewrite(text: &str, original: &Value, updated: &Value) -> Option<String> {
+    let edits = edits(original, updated)?;
+
+    let mut lines: Vec<String> = text.split_inclusive('\n').map(|l| l.to_owned()).collect();
+
+    // replacements keep the lines where they are, hence they go first
+    for edit in &edits {
+        if let Edit::Replace(path, value) = edit {
+            replace(&mut lines, path, value)?;
+        }
+    }
+
+    // deletions are located on the original lines, and then applied bottom-up
+    // so that each of them leaves the lines of the following ones untouched
+    let mut deletions = edits
+        .iter()
+        .filter_map(|edit| match edit {
+            Edit::Remove(path) => Some(locate(&lines, path)),
+            Edit::Replace(..) | Edit::Append(..) => None,
+        })
+        .collect::<Option<Vec<Deletion>>>()?;
+    deletions.sort_by_key(|d| Reverse(d.start));
+
+    for deletion in deletions {
+        if let Some(dash) = &deletion.moved_dash {
+            let line = lines.get_mut(deletion.end)?;
+            line.replace_range(..dash.len(), dash);
+        }
+        lines.drain(deletion.start..deletion.end);
+    }
+
+    // the sequences left with no item are written as empty ones, not to become null
+    let mut emptied: Vec<&[Step]> = edits
+        .iter()
+        .filter_map(|edit| match edit {
+            Edit::Remove(path) if matches!(path.last(), Some(Step::Index(_))) => Some(&path[..path.len() - 1]),
+            _ => None,
+        })
+        .filter(|path| value_at(updated, path).is_some_and(|s| s.as_sequence().is_some_and(|s| s.is_empty())))
+        .collect();
+    emptied.dedup_by_key(|path| path.len());
+
+    for path in emptied {
+        empty(&mut lines, path)?;
+    }
+
+    // appended items follow whatever is left of their sequences
+    for edit in &edits {
+        if let Edit::Append(path, value) = edit {
+            append(&mut lines, path, value)?;
+        }
+    }
+
+    let rewritten = lines.concat();
+
+    // the rewriting is trusted only if it declares exactly the updated document
+    let reparsed: Value = serde_yaml::from_str(&rewritten).ok()?;
+    (reparsed == *updated).then_some(rewritten)
+}
+
+/// It lists the edits turning `original` into `updated`, i.e., the nodes of
+/// `original` missing in `updated`, the scalars replaced in `updated` and the
+/// scalars appended to its sequences, provided that `updated` is obtained from
+/// `original` by such edits only
+pub fn edits(original: &Value, updated: &Value) -> Option<Vec<Edit>> {
+    let mut edits: Vec<Edit> = vec![];
+    diff(original, updated, &mut vec![], &mut edits)?;
+    Some(edits)
+}
+
+/// It collects the nodes of `original` that are missing in `updated`, the
+/// scalars that `updated` replaces and those it appends to sequences, failing
+/// if they differ in any other way
+fn diff(
+    original: &Value,
+    updated: &Value,
+    path: &mut Vec<Step>,
+    edits: &mut Vec<Edit>
+) -> Option<()> {
+    if original == updated {
+        return Some(());
+    }
+
+    match (original, updated) {
+        (Value::Mapping(original), Value::Mapping(updated)) => {
+            if updated.keys().any(|k| !original.contains_key(k)) {
+                return None;
+            }
+
+            for (key, value) in original {
+                path.push(Step::Key(key.as_str()?.to_owned()));
+                match updated.get(key) {
+                    Some(updated_value) => diff(value, updated_value, path, edits)?,
+                    None => edits.push(Edit::Remove(path.clone())),
+                }
+                path.pop();
+            }
+
+            Some(())
+        },
+        (Value::Sequence(original), Value::Sequence(updated)) => {
+            // no item is removed, hence each item is matched with its updated version
+            if original.len() == updated.len() {
+                for (i, (item, updated_item)) in original.iter().zip(updated).enumerate() {
+                    path.push(Step::Index(i));
+                    diff(item, updated_item, path, edits)?;
+                    path.pop();
+                }
+                return Some(());
+            }
+
+            // the items kept are matched in order with the original ones...
+            let mut kept = updated.iter().peekable();
+
+            for (i, item) in original.iter().enumerate() {
+                path.push(Step::Index(i));
+                match kept.next_if(|k| same_item(item, k)) {
+                    Some(updated_item) => diff(item, updated_item, path, edits)?,
+                    None => edits.push(Edit::Remove(path.clone())),
+                }
+                path.pop();
+            }
+
+            // ...and the others must be scalars appended to them
+            for item in kept {
+                if matches!(item, Value::Mapping(_) | Value::Sequence(_) | Value::Tagged(_)) {
+                    return None;
+                }
+                edits.push(Edit::Append(path.clone(), item.clone()));
+            }
+
+            Some(())
+        },
+        (Value::Mapping(_) | Value::Sequence(_) | Value::Tagged(_), _) => None,
+        (_, Value::Mapping(_) | Value::Sequence(_) | Value::Tagged(_)) => None,
+        _ => {
+            edits.push(Edit::Replace(path.clone(), updated.clone()));
+            Some(())
+        },
+    }
+}
+
+/// It tells whether two sequence items are versions of the same item,
+/// i.e., they are equal or they are mappings with the same name
+fn same_item(a: &Value, b: &Value) -> bool {
+    a == b || matches!((a.get("name"), b.get("name")), (Some(x), Some(y)) if x == y)
+}
+
+/// It follows `steps` from the root of a document to the block collection they lead to
+fn walk(lines: &[String], steps: &[Step]) -> Option<Node> {
+    let first = (0..lines.len()).find(|i| !is_blank(&lines[*i]))?;
+    let mut node = Node { first, end: lines.len(), column: indent(&lines[first]) };
+
+    for step in steps {
+        node = match step {
+            Step::Key(key) => {
+                let (line, end) = find_entry(lines, node, key)?;
+                value_of(lines, line, end, node.column)?
+            },
+            Step::Index(index) => {
+                let (line, end) = find_item(lines, node, *index)?;
+                content_of(lines, line, end, node.column)?
+            },
+        };
+    }
+
+    Some(node)
+}
+
+/// It locates the lines to delete for removing the node at `path`
+fn locate(lines: &[String], path: &[Step]) -> Option<Deletion> {
+    let (last, steps) = path.split_last()?;
+    let node = walk(lines, steps)?;
+
+    match last {
+        Step::Key(key) => {
+            let (line, end) = find_entry(lines, node, key)?;
+            let prefix = &lines[line][..node.column];
+
+            if !prefix.contains('-') {
+                return Some(Deletion { start: line, end, moved_dash: None });
+            }
+
+            // the entry shares its line with the dash of a sequence item,
+            // hence the dash is moved to the next entry of the item (if any)
+            let next = (end..node.end).find(|i| !is_blank(&lines[*i]))?;
+            Some(Deletion { start: line, end: next, moved_dash: Some(prefix.to_owned()) })
+        },
+        Step::Index(index) => {
+            let (line, end) = find_item(lines, node, *index)?;
+            Some(Deletion { start: line, end, moved_dash: None })
+        },
+    }
+}
+
+/// It replaces the scalar value of the entry at `path`, provided that the
+/// value is written on the line of its key. The quotes of the value, as well
+/// as the comment following it, are kept.
+fn replace(lines: &mut [String], path: &[Step], value: &Value) -> Option<()> {
+    let (Step::Key(key), steps) = path.split_last()? else { return None };
+    let node = walk(lines, steps)?;
+
+    let (line, end) = find_entry(lines, node, key)?;
+    if end != line + 1 {
+        return None;
+    }
+
+    let text = lines[line].trim_end_matches('\n');
+    let colon = node.column + text[node.column..].find(':')?;
+    let written = text[colon + 1..].trim_start();
+    let spaces = &text[colon + 1..text.len() - written.len()];
+
+    // the original scalar ends at its closing quote, or before its comment
+    let (scalar_end, rendered) = match (written.chars().next(), value.as_str()) {
+        (Some('"'), Some(string)) => {
+            // escaped quotes do not close the scalar
+            let mut is_escaped = false;
+            let closing = 1 + written[1..].find(|c| {
+                let is_closing = c == '"' && !is_escaped;
+                is_escaped = c == '\\' && !is_escaped;
+                is_closing
+            })?;
+            (closing + 1, serde_json::to_string(string).ok()?)
+        },
+        (Some('\''), Some(string)) => {
+            let closing = 1 + written[1..].find('\'')?;
+            (closing + 1, format!("'{}'", string.replace('\'', "''")))
+        },
+        _ => {
+            let rendered = serde_yaml::to_string(value).ok()?;
+            let scalar_end = written[..written.find(" #").unwrap_or(written.len())].trim_end().len();
+            (scalar_end, rendered.trim_end_matches('\n').to_owned())
+        },
+    };
+
+    if rendered.contains('\n') {
+        return None;
+    }
+
+    let newline = if lines[line].ends_with('\n') { "\n" } else { "" };
+    lines[line] = format!("{}:{}{}{}{}", &text[..colon], spaces, rendered, &written[scalar_end..], newline);
+
+    Some(())
+}
+
+/// It appends a scalar to the sequence of the entry at `path`, as a new item
+/// following its last one. An empty flow sequence (i.e., `[]`) is turned into
+/// a block sequence, indented deeper than its key.
+fn append(lines: &mut Vec<String>, path: &[Step], value: &Value) -> Option<()> {
+    let (Step::Key(key), steps) = path.split_last()? else { return None };
+    let node = walk(lines, steps)?;
+    let (line, end) = find_entry(lines, node, key)?;
+
+    let rendered = serde_yaml::to_string(value).ok()?;
+    let rendered = rendered.trim_end_matches('\n');
+    if rendered.contains('\n') {
+        return None;
+    }
+
+    if let Some(sequence) = value_of(lines, line, end, node.column) {
+        if !is_dash(&lines[sequence.first], sequence.column) {
+            return None;
+        }
+
+        // trailing comments are left to what follows the sequence
+        let last = (sequence.first..end).rev().find(|i| !is_blank(&lines[*i]))?;
+        if !lines[last].ends_with('\n') {
+            lines[last].push('\n');
+        }
+        lines.insert(last + 1, format!("{}- {}\n", " ".repeat(sequence.column), rendered));
+        return Some(());
+    }
+
+    let text = lines[line].trim_end_matches('\n');
+    let colon = node.column + text[node.column..].find(':')?;
+    let written = &text[colon + 1..];
+    let comment = written.find(" #").map_or("", |i| &written[i..]);
+    if written[..written.len() - comment.len()].trim() != "[]" {
+        return None;
+    }
+
+    lines[line] = format!("{}:{}\n", &text[..colon], comment);
+    lines.insert(line + 1, format!("{}- {}\n", " ".repeat(node.column + 2), rendered));
+    Some(())
+}
+
+/// It writes an empty flow sequence (i.e., `[]`) as the value of the entry
+/// at `path`, which has been left with no value
+fn empty(lines: &mut [String], path: &[Step]) -> Option<()> {
+    let (Step::Key(key), steps) = path.split_last()? else { return None };
+    let node = walk(lines, steps)?;
+    let (line, _) = find_entry(lines, node, key)?;
+
+    let text = lines[line].trim_end_matches('\n');
+    let colon = node.column + text[node.column..].find(':')?;
+    let written = &text[colon + 1..];
+    let comment = written.find('#').map_or("", |i| &written[i..]);
+    if !written[..written.len() - comment.len()].trim().is_empty() {
+        return None;
+    }
+
+    let comment = if comment.is_empty() { String::new() } else { format!(" {}", comment) };
+    let newline = if lines[line].ends_with('\n') { "\n" } else { "" };
+    lines[line] = format!("{}: []{}{}", &text[..colon], comment, newline);
+    Some(())
+}
+
+/// It returns the node at `path` of a YAML tree
+fn value_at<'a>(value: &'a Value, path: &[Step]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |node, step| match step {
+        Step::Key(key) => node.get(key.as_str()),
+        Step::Index(index) => node.get(index),
+    })
+}
+
+/// It finds the entry `key` of a block mapping, returning its first line and
+/// the line after its value
+fn find_entry(lines: &[String], node: Node, key: &str) -> Option<(usize, usize)> {
+    let line = (node.first..node.end).find(|i| key_at(&lines[*i], node.column) == Some(key))?;
+
+    // the value spans the lines indented more than the key, as well as
+    // the items of a block sequence indented as the key
+    let mut end = line + 1;
+    for (i, current) in lines.iter().enumerate().take(node.end).skip(line + 1) {
+        if is_blank(current) { continue }
+
+        let indentation = indent(current);
+        if indentation > node.column || (indentation == node.column && is_dash(current, node.column)) {
+            end = i + 1;
+        } else {
+            break;
+        }
+    }
+
+    Some((line, end))
+}
+
+/// It finds the `index`-th item of a block sequence, returning its first line
+/// and the line after its content
+fn find_item(lines: &[String], node: Node, index: usize) -> Option<(usize, usize)> {
+    let starts: Vec<usize> = (node.first..node.end)
+        .filter(|i| indent(&lines[*i]) == node.column && is_dash(&lines[*i], node.column))
+        .collect();
+
+    let line = *starts.get(index)?;
+    let limit = starts.get(index + 1).copied().unwrap_or(node.end);
+
+    // trailing comments are left to what follows the item
+    let end = (line + 1..limit)
+        .rev()
+        .find(|i| !is_blank(&lines[*i]))
+        .map_or(line + 1, |i| i + 1);
+
+    Some((line, end))
+}
+
+/// It returns the node of the value of the entry in the lines [line, end),
+/// provided that the value is a block collection
+fn value_of(lines: &[String], line: usize, end: usize, column: usize) -> Option<Node> {
+    let after_key = lines[line][column..].split_once(':')?.1;
+    if !is_blank(after_key) {
+        return None;
+    }
+
+    let first = (line + 1..end).find(|i| !is_blank(&lines[*i]))?;
+    Some(Node { first, end, column: indent(&lines[first]) })
+}
+
+/// It returns the node of the content of the sequence item in the lines
+/// [line, end), whose dash is at `column`
+fn content_of(lines: &[String], line: usize, end: usize, column: usize) -> Option<Node> {
+    let after_dash = &lines[line][column + 1..];
+
+    if is_blank(after_dash) {
+        let first = (line + 1..end).find(|i| !is_blank(&lines[*i]))?;
+        return Some(Node { first, end, column: indent(&lines[first]) });
+    }
+
+    let spaces = after_dash.len() - after_dash.trim_start_matches(' ').len();
+    Some(Node { first: line, end, column: column + 1 + spaces })
+}
+
+/// It returns the key starting at `column` of a line, provided that the line
+/// has only indentation and sequence dashes before it
+fn key_at(line: &str, column: usize) -> Option<&str> {
+    let prefix = line.get(..column)?;
+    let is_indentation = prefix
+        .split(' ')
+        .all(|part| part.is_empty() || part == "-");
+    if !is_indentation || prefix.ends_with('-') {
+        return None;
+    }
+
+    let rest = &line[column..];
+    let (key, after) = match rest.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let closing = rest[1..].find(quote)? + 1;
+            (&rest[1..closing], &rest[closing + 1..])
+        },
+        ' ' | '-' | '#' => return None,
+        _ => {
+            let colon = rest.find(':')?;
+            (&rest[..colon], &rest[colon..])
+        },
+    };
+
+    let after = after.strip_prefix(':')?;
+    (after.is_empty() || after.starts_with(char::is_whitespace)).then_some(key)
+}
+
+fn is_dash(line: &str, column: usize) -> bool {
+    line.get(column..)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+fn is_blank(line: &str) -> bool {
+    let content = line.trim();
+    content.is_empty() || content.starts_with('#')
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYMENT: &str = "\
+# the cart workload
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: cart # kept as is
+spec:
+  template:
+    spec:
+      hostNetwork: true   # to be removed
+      containers:
+        - name: cart
+          image: 'cart:1.0'
+          env:
+            - name: DB
+              value: \"10.0.0.7:27017\" # hardcoded
+            - name: MODE
+              value: prod
+";
+
+    fn updated(text: &str, update: impl FnOnce(&mut Value)) -> (Value, Value) {
+        let original: Value = serde_yaml::from_str(text).unwrap();
+        let mut updated = original.clone();
+        update(&mut updated);
+        (original, updated)
+    }
+
+    #[test]
+    fn it_keeps_comments_and_quoting_when_editing() {
+        let (original, updated) = updated(DEPLOYMENT, |d| {
+            let spec = &mut d["spec"]["template"]["spec"];
+            spec.as_mapping_mut().unwrap().remove("hostNetwork");
+            spec["containers"][0]["env"][0]["value"] = Value::from("cart-db:27017");
+        });
+
+        let rewritten = rewrite(DEPLOYMENT, &original, &updated).unwrap();
+
+        assert_eq!(rewritten, DEPLOYMENT
+            .replace("      hostNetwork: true   # to be removed\n", "")
+            .replace("\"10.0.0.7:27017\"", "\"cart-db:27017\""));
+    }
+
+    #[test]
+    fn it_keeps_the_dash_when_removing_the_first_key_of_an_item() {
+        let (original, updated) = updated(DEPLOYMENT, |d| {
+            d["spec"]["template"]["spec"]["containers"][0].as_mapping_mut().unwrap().remove("name");
+        });
+
+        let rewritten = rewrite(DEPLOYMENT, &original, &updated).unwrap();
+
+        assert!(rewritten.contains("      containers:\n        - image: 'cart:1.0'\n          env:\n"));
+    }
+
+    #[test]
+    fn it_appends_to_and_empties_sequences() {
+        let text = "ignored: # files\n  - a.yaml # scratch\nkept: [x]\n";
+        let (original, appended) = updated(text, |c| {
+            c["ignored"].as_sequence_mut().unwrap().push(Value::from("b.yaml"));
+        });
+        assert_eq!(
+            rewrite(text, &original, &appended).unwrap(),
+            "ignored: # files\n  - a.yaml # scratch\n  - b.yaml\nkept: [x]\n"
+        );
+
+        let (original, emptied) = updated(text, |c| c["ignored"] = Value::Sequence(vec![]));
+        assert_eq!(rewrite(text, &original, &emptied).unwrap(), "ignored: [] # files\nkept: [x]\n");
+    }
+
+    #[test]
+    fn it_falls_back_when_the_edits_cannot_be_followed() {
+        // keys added to mappings are not edits
+        let (original, updated_with_key) = updated(DEPLOYMENT, |d| {
+            d["metadata"]["namespace"] = Value::from("shop");
+        });
+        assert!(edits(&original, &updated_with_key).is_none());
+        assert!(rewrite(DEPLOYMENT, &original, &updated_with_key).is_none());
+
+        // flow style is not laid out line by line
+        let text = "spec: {hostNetwork: true, replicas: 1}\n";
+        let (original, updated) = updated(text, |d| {
+            d["spec"].as_mapping_mut().unwrap().remove("hostNetwork");
+        });
+        assert!(edits(&original, &updated).is_some());
+        assert!(rewrite(text, &original, &updated).is_none());
+    }
+}
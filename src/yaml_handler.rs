@@ -1,210 +1,344 @@
-use crate::{k8s_types::*, yaml_handler};
+use crate::k8s_types::*;
 use crate::{config_type::*};
-use std::collections::HashMap;
-use std::fs::File;
+use crate::error::Error;
+use crate::microtosca::MicroTosca;
+use crate::yaml_editor;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::vec;
-use std::{fs, io::Write};
+use std::fs;
+use std::io;
 use walkdir::WalkDir;
 use colored::Colorize;
 
-const CONFIG_PATH: &str = "./config.yaml";
+pub fn deployment_has_direct_access(deployment: &K8SManifest) -> bool {
+    let Some(pod) = deployment.pod_spec() else { return false };
 
-pub fn deployment_has_direct_access(deployment: K8SManifest) -> bool {
+    pod.host_network || pod.containers
+        .iter()
+        .filter_map(|container| container.ports.as_ref())
+        .any(|ports| ports.iter().any(|port| port.hostPort.is_some()))
+}
 
-    if let Some(host_network) = deployment.spec.hostNetwork {
-        if host_network {
-            return true;
-        }
-    }
+/// It lists recursively the manifest files inside the given folders,
+/// skipping the ones listed in `ignored_manifests`
+pub fn manifest_files(manifest_dirs: &[PathBuf], ignored_manifests: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let mut files: Vec<PathBuf> = vec![];
+    for (dir, entry) in manifest_dirs
+        .iter()
+        .flat_map(|dir| WalkDir::new(dir).follow_links(true).into_iter().map(move |e| (dir, e)))
+    {
+        // a missing or unreadable folder is not a folder with no manifests
+        let entry = entry.map_err(|e| {
+            let path = e.path().unwrap_or(dir).to_path_buf();
+            let message = e.to_string();
+            Error::io(&path, e.into_io_error().unwrap_or_else(|| io::Error::other(message)))
+        })?;
 
-    if let Some(containers) = deployment.spec.containers {
-        for container in containers {
-            if let Some(ports) = container.ports {
-                let has_host_port = ports.into_iter().any(|port| !port.hostPort.is_none());
-                if has_host_port {
-                    return true
-                }
-            }
+        let filename = entry.file_name().to_string_lossy();
+        let f = filename.to_string();
+
+        // Discard all manifests declared in the ignore list, either with or without extension
+        let is_ignored = ignored_manifests
+            .iter()
+            .any(|i| *i == f || Some(i.as_str()) == f.strip_suffix(".yaml"));
+
+        // kustomizations are not k8s objects, but they only tell how to build them
+        let is_kustomization = f == "kustomization.yaml";
+
+        if filename.ends_with(".yaml") && !is_ignored && !is_kustomization {
+            files.push(entry.path().to_path_buf());
         }
     }
 
-    if let Some(template) = deployment.spec.template {
-        if let Some(containers) = template.spec.containers {
-            for container in containers {
-                if let Some(ports) = container.ports {
-                    let has_host_port = ports.into_iter().any(|port| !port.hostPort.is_none());
-                    if has_host_port {
-                        return true
-                    }
-                }
-            }
+    Ok(files)
+}
+
+/// It read recursively all the k8s manifests inside the given folders,
+/// skipping the ones listed in `ignored_manifests`. Files are only read:
+/// the ones declaring multiple k8s components are left as they are.
+/// Files that cannot be parsed abort the parsing, unless `skip_invalid` is set:
+/// in such case they are reported as warnings and the other files are parsed.
+pub fn parse_manifests(
+    manifest_dirs: &[PathBuf],
+    ignored_manifests: &[String],
+    skip_invalid: bool,
+    log: bool
+) -> Result<Vec<K8SManifest>, Error> {
+    let mut manifests: Vec<K8SManifest> = vec![];
+    for path in manifest_files(manifest_dirs, ignored_manifests)? {
+        let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        if log { println!("[*] Parsing {}", filename); }
+
+        match parse_file(&path) {
+            Ok(parsed) => manifests.extend(parsed),
+            Err(error) if skip_invalid => warn_skipped(&error),
+            Err(error) => return Err(error),
         }
     }
-    
-    false
-}
 
-pub fn get_deployment_named(name: String, manifests: &Vec<K8SManifest>) -> Option<K8SManifest> {
-    let deployments = get_deployments_pods(manifests);
+    if log { println!("{}", "[*] Parsing done\n".green().bold()); }
+    Ok(manifests)
+}
 
-    deployments
-        .into_iter()
-        .find(|d| {
-            return *d.metadata.name == name
-        })
+/// It parses all the k8s components declared in a manifest file
+fn parse_file(path: &Path) -> Result<Vec<K8SManifest>, Error> {
+    let content = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    parse_documents(path, &content)
 }
 
-/// It filters destination rules from all the manifests declared
-pub fn get_destination_rules(manifest: &Vec<K8SManifest>) -> Vec<K8SManifest> {
-    let v_services = manifest
-        .into_iter()
-        .filter(|man| man.kind == "DestinationRule")
-        .map(|man| man.clone())
-        .collect();
+/// It parses the k8s components declared in the documents of a YAML stream,
+/// where the stream can declare different k8s components in separate documents
+pub fn parse_documents(path: &Path, content: &str) -> Result<Vec<K8SManifest>, Error> {
+    let mut manifests: Vec<K8SManifest> = vec![];
+    let documents = locate_documents(content);
 
-    v_services
-}
+    for (document, de) in serde_yaml::Deserializer::from_str(content).enumerate() {
+        // the stream cannot be resumed after an error, hence the first one is returned
+        let parsed = Option::<Value>::deserialize(de)
+            .map_err(|e| Error::parse(path, e))?;
 
-/// It filters virtual services from all the manifests declared
-pub fn get_virtual_services(manifest: &Vec<K8SManifest>) -> Vec<K8SManifest> {
-    let v_services = manifest
-        .into_iter()
-        .filter(|man| man.kind == "VirtualService")
-        .map(|man| man.clone())
-        .collect();
+        // empty documents declare nothing
+        let Some(raw) = parsed else { continue };
 
-    v_services
-}
+        let line = documents.get(document).map_or(1, |d| d.line);
+        let mut manifest = K8SManifest::from_document(raw)
+            .map_err(|e| locate_error(path, content, documents.get(document), e))?;
 
-/// It filters services from all the manifests declared
-pub fn get_services(manifests: &Vec<K8SManifest>) -> Vec<K8SManifest> {
-    let services = manifests
-        .into_iter()
-        .filter(|man| man.kind == "Service")
-        .map(|man| man.clone())
-        .collect();
+        manifest.source = Some(Source { path: path.to_path_buf(), document, line });
+        manifests.push(manifest)
+    }
 
-    services
+    Ok(manifests)
 }
 
-/// It filters deployment or pod manifests from all the manifests declared
-pub fn get_deployments_pods(manifests: &Vec<K8SManifest>) -> Vec<K8SManifest> {
-    let deployment_manifests = manifests
-        .into_iter()
-        .filter(|man| man.kind == "Deployment" || man.kind == "Pod")
-        .map(|man| man.clone())
-        .collect();
+/// It locates an error raised while parsing an object out of its YAML tree,
+/// which has no positions, by parsing again the text of its document
+fn locate_error(path: &Path, content: &str, document: Option<&Document>, error: serde_yaml::Error) -> Error {
+    let Some(document) = document else { return Error::parse_at(path, 1, error) };
+
+    let reparsed = serde_yaml::from_str::<K8SManifest>(&content[document.range.clone()]);
+
+    match reparsed {
+        // the text may fail earlier than the tree, as it is parsed as a whole
+        Err(located) if located.location().is_some() && located.to_string().contains(&error.to_string()) => {
+            let lines_before = content[..document.range.start].matches('\n').count();
+
+            match Error::parse(path, located) {
+                Error::Parse { path, line, column, message } => Error::Parse {
+                    path,
+                    line: line.map(|l| l + lines_before),
+                    column,
+                    message,
+                },
+                other => other,
+            }
+        },
+        _ => Error::parse_at(path, document.line, error),
+    }
+}
 
-    deployment_manifests
+fn warn_skipped(error: &Error) {
+    eprintln!("{} {}", "[!] Skipping invalid manifest".yellow().bold(), error);
 }
 
-/// It read recursively all the k8s manifests inside the 'manifests' folder
-pub fn parse_manifests(log: bool) -> Vec<K8SManifest> {
-    let mut manifests: Vec<K8SManifest> = vec![];
-    for entry in WalkDir::new("./manifests")
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let filename = entry.file_name().to_string_lossy();
-        let f = filename.to_string();
+/// It splits each manifest file declaring different k8s components into
+/// a file per component, named `<name>-<kind>.yaml` (or `<name>.<namespace>-<kind>.yaml`
+/// if components in different namespaces share name and kind), and it returns the
+/// created files. Nothing is written if some file would be overwritten.
+pub fn split_manifests(
+    manifest_dirs: &[PathBuf],
+    ignored_manifests: &[String],
+    skip_invalid: bool
+) -> Result<Vec<PathBuf>, Error> {
+    let mut splits: Vec<(PathBuf, Vec<SplitComponent>)> = vec![];
+
+    for path in manifest_files(manifest_dirs, ignored_manifests)? {
+        let content = fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+
+        let manifests = match parse_documents(&path, &content) {
+            Ok(manifests) => manifests,
+            Err(error) if skip_invalid => {
+                warn_skipped(&error);
+                continue
+            },
+            Err(error) => return Err(error),
+        };
+
+        if manifests.len() <= 1 { continue }
+
+        let documents = locate_documents(&content);
+        let mut components = vec![];
+
+        for manifest in manifests {
+            // objects named through `generateName` are named after its prefix
+            let name = match manifest.metadata.name.is_empty() {
+                false => manifest.metadata.name.clone(),
+                true => manifest.raw["metadata"]["generateName"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .trim_end_matches('-')
+                    .to_owned(),
+            };
+
+            // the component is copied as it is written, if it can be located
+            let document = manifest.source
+                .as_ref()
+                .and_then(|s| documents.get(s.document));
+
+            if name.is_empty() {
+                return Err(Error::Parse {
+                    path: path.clone(),
+                    line: document.map(|d| d.line),
+                    column: None,
+                    message: format!("cannot be split, as a {} has no name to name its file after", manifest.kind),
+                });
+            }
 
-        // Discard all manifests delcared in ignore-list.yaml
-        if filename.ends_with(".yaml") && !get_ignored_manifests().contains(&f) {
-            if log { println!("[*] Parsing {}", filename); }
-            let path = entry.path();
-            let ref manifest_string = fs::read_to_string(path).expect(&filename.to_string());
-
-            /*
-            Case when we have a manifest which declares different k8s components
-            separated by "---"
-            */
-            let ref sub_manifests = yaml_handler::unpack(manifest_string);
-
-            if sub_manifests.len() > 1 {
-                // delete the manifest
-                fs::remove_file(path)
-                    .expect("Cannot delete redundant manifest");
-
-                for man in sub_manifests {
-                    let mut man_path = String::from(path.to_str().unwrap());
-                    
-                    let components = path.to_str().unwrap().split("/");
-                    let last_component = components.last().unwrap();
-
-                    man_path = remove_suffix(&man_path, last_component).to_string();
-
-                    // ? converting man so that we can retrieve the deployment name
-                    let converted_man: K8SManifest = serde_yaml::from_str(&man).unwrap();   
-                    let man_name = converted_man.metadata.name;
-                    let man_kind = converted_man.kind;
-
-                    man_path.push_str(&man_name);
-                    man_path.push_str("-");
-                    man_path.push_str(&man_kind);
-                    man_path.push_str(".yaml");
-
-                    let mut file = File::create(man_path)
-                        .expect("Error encountered while unpacking manifest!");
-                    
-                    let res = file.write_all(man.as_bytes());
-
-                    if res.is_err() {
-                        println!("Error while writing a new pod");
-                    }
+            let yaml = match document {
+                Some(document) => content[document.range.clone()].trim_start().to_owned(),
+                None => serde_yaml::to_string(&manifest.raw).map_err(|e| Error::serialize(&path, e))?,
+            };
 
-                }
-            }
+            components.push(SplitComponent {
+                file: format!("{}-{}.yaml", name, manifest.kind),
+                qualified: format!("{}-{}.yaml", file_stem(&name, &manifest.metadata.namespace), manifest.kind),
+                yaml,
+            });
+        }
 
-            // deserializing manifests
-            for m in sub_manifests {
-                let converted_manifest: K8SManifest = serde_yaml::from_str(&m).unwrap();
-                manifests.push(converted_manifest)
+        splits.push((path, components));
+    }
+
+    // the files are checked to be distinct, both among them and from the
+    // existing ones, before any of them is written
+    let mut targets: Vec<(PathBuf, &String)> = vec![];
+
+    for (path, components) in &splits {
+        for component in components {
+            let clashes = splits
+                .iter()
+                .flat_map(|(p, c)| c.iter().map(move |c| p.with_file_name(&c.file)))
+                .filter(|p| *p == path.with_file_name(&component.file))
+                .count() > 1;
+
+            let target = match clashes {
+                true => path.with_file_name(&component.qualified),
+                false => path.with_file_name(&component.file),
+            };
+
+            let is_taken = targets.iter().any(|(t, _)| *t == target)
+                || (target != *path && (target.exists() || splits.iter().any(|(p, _)| *p == target)));
+
+            if is_taken {
+                let reason = format!("cannot be split, as {} would be overwritten", target.display());
+                return Err(Error::io(path, io::Error::new(io::ErrorKind::AlreadyExists, reason)));
             }
+
+            targets.push((target, &component.yaml));
+        }
+    }
+
+    for (target, yaml) in &targets {
+        fs::write(target, yaml).map_err(|e| Error::io(target, e))?;
+    }
+
+    // the original manifests are deleted only once all their components are safe
+    for (path, _) in &splits {
+        if !targets.iter().any(|(target, _)| target == path) {
+            fs::remove_file(path).map_err(|e| Error::io(path, e))?;
         }
-    } 
+    }
+
+    Ok(targets.into_iter().map(|(target, _)| target).collect())
+}
+
+/// A component of a manifest file to be split, with the name of its file and
+/// the one qualified by its namespace, used if the former is not unique
+struct SplitComponent {
+    file: String,
+    qualified: String,
+    yaml: String,
+}
 
-    if log { println!("{}", format!("[*] Parsing done\n").green().bold()); }
-    manifests
+pub fn get_config(config_path: &Path) -> Result<Config, Error> {
+    internal_read(config_path)
 }
 
-fn remove_suffix<'a>(s: &'a str, p: &str) -> &'a str {
-    if s.ends_with(p) {
-        &s[..s.len() - p.len()]
-    } else {
-        s
+pub fn save_config(config_path: &Path, config: &Config) -> Result<(), Error> {
+    internal_write(config_path, config)
+}
+
+/// It sets the manifests ignored by the config, by only changing the lines of
+/// those added or removed, so that the comments and the layout of the rest of
+/// the config are kept. The whole config is rewritten if its layout cannot be followed.
+pub fn save_ignored_manifests(config_path: &Path, ignored_manifests: &[String]) -> Result<(), Error> {
+    let text = fs::read_to_string(config_path).map_err(|e| Error::io(config_path, e))?;
+    let original: Value = serde_yaml::from_str(&text).map_err(|e| Error::parse(config_path, e))?;
+
+    let mut updated = original.clone();
+    updated["ignored_manifests"] = ignored_manifests.iter().map(|f| Value::from(f.as_str())).collect();
+
+    match yaml_editor::rewrite(&text, &original, &updated) {
+        Some(rewritten) => fs::write(config_path, rewritten).map_err(|e| Error::io(config_path, e)),
+        None => {
+            let mut config = get_config(config_path)?;
+            config.ignored_manifests = ignored_manifests.to_vec();
+            save_config(config_path, &config)
+        },
     }
 }
 
-pub fn get_config() -> Config {
-    let converted_config: Config = internal_read(CONFIG_PATH.to_owned());
+/// It reads a microTOSCA topology
+pub fn get_microtosca(path: &Path) -> Result<MicroTosca, Error> {
+    internal_read(path)
+}
 
-    converted_config
+pub fn save_microtosca(path: &Path, model: &MicroTosca) -> Result<(), Error> {
+    internal_write(path, model)
 }
 
-pub fn get_ignored_manifests() -> Vec<String> {
-    get_config().ignored_manifests
+/// It reads the registry of known images, which is empty until the first image is added
+pub fn get_known_images(registry_path: &Path) -> Result<Vec<KnownImage>, Error> {
+    if !registry_path.exists() {
+        return Ok(vec![]);
+    }
+
+    let images: Option<Vec<KnownImage>> = internal_read(registry_path)?;
+    Ok(images.unwrap_or_default())
 }
 
-pub fn create_virtual_service(depl_name: String) {
-    let mut path = String::from("./manifests/");
-    path.push_str(&depl_name);
-    path.push_str("-virtual-service.yaml");
+pub fn save_known_images(registry_path: &Path, images: &[KnownImage]) -> Result<(), Error> {
+    internal_write(registry_path, &images)
+}
 
-    let mut file = File::create(path)
-        .expect("Error encountered while creating a new destination rule!");
+/// It renders a new virtual service with a timeout for `depl_name`, returning
+/// the file it should be written to together with its content
+pub fn virtual_service_for(
+    manifests_dir: &Path,
+    depl_name: String,
+    namespace: Option<String>
+) -> Result<(PathBuf, String), Error> {
+    let path = manifests_dir.join(format!("{}-virtual-service.yaml", file_stem(&depl_name, &namespace)));
 
     let vs = K8SManifest {
         api_version: String::from("networking.istio.io/v1alpha3"),
         kind: String::from("VirtualService"),
-        metadata: Metadata { name: depl_name.clone(), labels: None },
+        metadata: Metadata { name: depl_name.clone(), namespace, labels: None },
         spec: Spec { 
             initContainers: None,
             containers: None,
             volumes: None,
             template: None,
+            job_template: None,
             hostNetwork: None,
             selector: None,
+            ports: None,
+            service_type: None,
             hosts: Some(vec![depl_name.clone()]),
             host: None,
             http: Some(vec![HttpSpec { route: 
@@ -213,7 +347,7 @@ pub fn create_virtual_service(depl_name: String) {
                         host: depl_name.clone()
                     }
                 }],
-                timeout: String::from("0.4s")
+                timeout: Some(String::from("0.4s"))
             }]),
             trafficPolicy: None,
             replicas: None,
@@ -221,37 +355,38 @@ pub fn create_virtual_service(depl_name: String) {
             service_account_name: None,
             termination_grace_period_seconds: None,
             security_context: None,
-        }
+        },
+        source: None,
+        raw: Value::Null,
     };
 
-    let yaml = serde_yaml::to_string(&vs).unwrap();
-
-    let res = file.write_all(yaml.as_bytes());
-
-    if res.is_err() {
-        println!("Error while writing a new virtual service");
-    }
+    let yaml = to_yaml(&path, &vs)?;
+    Ok((path, yaml))
 }
 
-pub fn create_pod_from(container: &Container) {
-    let mut path = String::from("./manifests/");
-    path.push_str(&container.name);
-    path.push_str(".yaml");
-    
-    let mut file = File::create(path)
-        .expect("Error encountered while creating a new pod!");
+/// It renders a new pod running the given container, which is copied as it is
+pub fn pod_from(
+    manifests_dir: &Path,
+    container: &Value,
+    namespace: Option<String>
+) -> Result<(PathBuf, String), Error> {
+    let name = container.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+    let path = manifests_dir.join(format!("{}.yaml", file_stem(name, &namespace)));
 
     let manifest = K8SManifest {
         api_version: String::from("apps/v1"),
         kind: String::from("Pod"),
-        metadata: Metadata { name: container.name.clone(), labels: None },
+        metadata: Metadata { name: name.to_owned(), namespace, labels: None },
         spec: Spec { 
             initContainers: None,
-            containers: Some(vec![container.clone()]),
+            containers: None,
             volumes: None,
             template: None,
+            job_template: None,
             hostNetwork: None,
             selector: None,
+            ports: None,
+            service_type: None,
             hosts: None,
             host: None,
             http: None,
@@ -261,42 +396,47 @@ pub fn create_pod_from(container: &Container) {
             service_account_name: None,
             termination_grace_period_seconds: None,
             security_context: None,
-        }
+        },
+        source: None,
+        raw: Value::Null,
     };
 
-    let yaml = serde_yaml::to_string(&manifest).unwrap();
+    let mut pod = serde_yaml::to_value(&manifest).map_err(|e| Error::serialize(&path, e))?;
+    pod["spec"]["containers"] = Value::Sequence(vec![container.clone()]);
 
-    let res = file.write_all(yaml.as_bytes());
-
-    if res.is_err() {
-        println!("Error while writing a new pod");
-    }
+    let yaml = to_yaml(&path, &pod)?;
+    Ok((path, yaml))
 }
 
-pub fn create_service_from(name: String) {
-    let mut path = String::from("./manifests/");
-    path.push_str(&name);
-    path.push_str("-srv");
-    path.push_str(".yaml");
-
-    let mut file = File::create(path)
-        .expect("Error encountered while creating a new service!");
-
-    let mut selector = HashMap::new();
-
-    selector.insert(String::from("app"), serde_json::Value::String(name.clone()));
+/// It renders a new k8s service named `name`, selecting the pods with the given labels
+pub fn service_for(
+    manifests_dir: &Path,
+    name: String,
+    namespace: Option<String>,
+    labels: &Labels
+) -> Result<(PathBuf, String), Error> {
+    let path = manifests_dir.join(format!("{}-srv.yaml", file_stem(&name, &namespace)));
+
+    let selector: BTreeMap<String, serde_json::Value> = labels
+        .pairs()
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), serde_json::Value::String(value.to_owned())))
+        .collect();
 
     let service_manifest = K8SManifest {
         api_version: "v1".to_string(),
         kind: "Service".to_string(),
-        metadata: Metadata { name: name.clone(), labels: None },
+        metadata: Metadata { name: name.clone(), namespace, labels: None },
         spec: Spec { 
             initContainers: None,
             containers: None,
             volumes: None,
             template: None,
+            job_template: None,
             hostNetwork: None,
             selector: Some(selector),
+            ports: None,
+            service_type: None,
             hosts: None,
             host: None,
             http: None,
@@ -306,64 +446,330 @@ pub fn create_service_from(name: String) {
             service_account_name: None,
             termination_grace_period_seconds: None,
             security_context: None,
-        }
+        },
+        source: None,
+        raw: Value::Null,
+    };
+
+    let yaml = to_yaml(&path, &service_manifest)?;
+    Ok((path, yaml))
+}
+
+/// It renders a datastore named `name` running `image`, i.e., a deployment
+/// with a single replica together with the k8s service reaching it
+pub fn datastore_for(
+    manifests_dir: &Path,
+    name: String,
+    namespace: Option<String>,
+    image: String,
+    port: Option<i32>
+) -> Result<(PathBuf, String), Error> {
+    let path = manifests_dir.join(format!("{}-datastore.yaml", file_stem(&name, &namespace)));
+
+    let mut labels = Labels::default();
+    labels.0.insert(String::from("app"), name.clone());
+
+    let container = Container {
+        name: name.clone(),
+        security_context: None,
+        image: Some(image),
+        ports: port.map(|port| vec![Port {
+            name: None,
+            hostPort: None,
+            container_port: Some(port),
+            protocol: None,
+        }]),
+        readiness_probe: None,
+        liveness_probe: None,
+        resources: None,
+        image_pull_policy: None,
+        env: None,
     };
 
-    let yaml = serde_yaml::to_string(&service_manifest).unwrap();
-    
-    let res = file.write_all(yaml.as_bytes());
+    let mut selector: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+    selector.insert(String::from("matchLabels"), serde_json::json!({ "app": name }));
 
-    if res.is_err() {
-        println!("Error raised while writing a new k8s service");
+    let deployment = K8SManifest {
+        api_version: String::from("apps/v1"),
+        kind: String::from("Deployment"),
+        metadata: Metadata { name: name.clone(), namespace: namespace.clone(), labels: None },
+        spec: Spec {
+            replicas: Some(1),
+            selector: Some(selector),
+            template: Some(Template {
+                spec: TemplateSpec {
+                    initContainers: None,
+                    containers: Some(vec![container]),
+                    volumes: None,
+                    hostNetwork: None,
+                },
+                metadata: Some(MetadataTemplate { labels: labels.clone() }),
+            }),
+            ..Default::default()
+        },
+        source: None,
+        raw: Value::Null,
+    };
+
+    let service = K8SManifest {
+        api_version: String::from("v1"),
+        kind: String::from("Service"),
+        metadata: Metadata { name: name.clone(), namespace, labels: None },
+        spec: Spec {
+            selector: Some(
+                labels.pairs()
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), serde_json::Value::String(value.to_owned())))
+                    .collect()
+            ),
+            ports: port.map(|port| vec![ServicePort {
+                name: None,
+                protocol: None,
+                port: Some(port),
+                target_port: None,
+                node_port: None,
+            }]),
+            ..Default::default()
+        },
+        source: None,
+        raw: Value::Null,
+    };
+
+    let yaml = format!("{}---\n{}", to_yaml(&path, &deployment)?, to_yaml(&path, &service)?);
+    Ok((path, yaml))
+}
+
+/// It renders a new persistent volume claim with the spec of the claim `like`
+/// (if declared), but for the volume it is bound to, or requesting 1Gi otherwise
+pub fn volume_claim_for(
+    manifests_dir: &Path,
+    name: String,
+    namespace: Option<String>,
+    like: Option<&K8SManifest>
+) -> Result<(PathBuf, String), Error> {
+    let path = manifests_dir.join(format!("{}-pvc.yaml", file_stem(&name, &namespace)));
+
+    let manifest = K8SManifest {
+        api_version: String::from("v1"),
+        kind: String::from("PersistentVolumeClaim"),
+        metadata: Metadata { name, namespace, labels: None },
+        spec: Spec::default(),
+        source: None,
+        raw: Value::Null,
+    };
+
+    let spec = match like.and_then(|claim| claim.raw.get("spec")) {
+        Some(Value::Mapping(spec)) => {
+            let mut spec = spec.clone();
+            spec.remove("volumeName");
+            Value::Mapping(spec)
+        },
+        _ => serde_yaml::from_str("{accessModes: [ReadWriteOnce], resources: {requests: {storage: 1Gi}}}")
+            .map_err(|e| Error::parse(&path, e))?,
+    };
+
+    let mut claim = serde_yaml::to_value(&manifest).map_err(|e| Error::serialize(&path, e))?;
+    claim["spec"] = spec;
+
+    let yaml = to_yaml(&path, &claim)?;
+    Ok((path, yaml))
+}
+
+/// It returns the stem of the files generated for the object `name`, which
+/// is qualified as a host (i.e., `name.namespace`) when it has a namespace
+fn file_stem(name: &str, namespace: &Option<String>) -> String {
+    match namespace {
+        Some(namespace) => format!("{}.{}", name, namespace),
+        None => name.to_owned(),
     }
 }
 
-pub fn update_manifest(manifest: &K8SManifest, filename: String) {
+/// It updates the content of the manifest file the given manifest has been read
+/// from, so that its document declares the `updated` YAML tree instead. It returns
+/// `None` if the document does not declare the manifest anymore, e.g., if the
+/// file has been edited meanwhile. The formatting of what is kept is preserved.
+pub fn update_document(
+    path: &Path,
+    content: &str,
+    manifest: &K8SManifest,
+    updated: &Value
+) -> Result<Option<String>, Error> {
+    let Some(source) = &manifest.source else { return Ok(None) };
+    let Some(target) = locate_documents(content).into_iter().nth(source.document) else { return Ok(None) };
+
+    let text = &content[target.range.clone()];
+    let original: Value = serde_yaml::from_str(text).map_err(|e| Error::parse(path, e))?;
+
+    if original != manifest.raw {
+        return Ok(None);
+    }
+
+    // only the lines of what the refactoring removed are changed, if possible
+    let yaml = match yaml_editor::rewrite(text, &original, updated) {
+        Some(rewritten) => rewritten,
+        None => {
+            // the replaced document must start on a line of its own
+            let separator = if content[..target.range.start].ends_with('\n')
+                || target.range.start == 0 { "" } else { "\n" };
 
-    for entry in WalkDir::new("./manifests")
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let file_name = entry.file_name().to_string_lossy();
-        let f = file_name.to_string();
-        let path = entry.path().to_str();
-
-        if f == filename {
-            if let Some(path) = path {
-                let yaml = serde_yaml::to_string(&manifest).unwrap();
-                let mut f = fs::OpenOptions::new()
-                    .write(true)
-                        .truncate(true)
-                        .open(path)
-                        .expect("Unable to open the file");
-                    f.write_all(yaml.as_bytes()).expect("Unable to write all");
-                    f.flush().expect("Unable to flush");
-                fs::write(path, yaml).expect("Unable to write file");
-            
-            }
+            format!("{}{}", separator, to_yaml(path, updated)?)
         }
-        
-    }
+    };
+
+    Ok(Some(format!(
+        "{}{}{}",
+        &content[..target.range.start],
+        yaml,
+        &content[target.range.end..]
+    )))
 }
 
 /// It reads a file and then tries to parse to a DeserializeOwned T
-fn internal_read<T: serde::de::DeserializeOwned>(filename: String) -> T {
-    let ref file_string = fs::read_to_string(filename).expect("Expecting known-images.yaml exists");
+fn internal_read<T: serde::de::DeserializeOwned>(filename: &Path) -> Result<T, Error> {
+    let file_string = fs::read_to_string(filename).map_err(|e| Error::io(filename, e))?;
+
+    serde_yaml::from_str(&file_string).map_err(|e| Error::parse(filename, e))
+}
+
+/// It serializes a T and then writes it to a file, replacing its content
+fn internal_write<T: serde::Serialize>(filename: &Path, object: &T) -> Result<(), Error> {
+    let yaml = to_yaml(filename, object)?;
+
+    fs::write(filename, yaml).map_err(|e| Error::io(filename, e))
+}
+
+fn to_yaml<T: serde::Serialize>(filename: &Path, object: &T) -> Result<String, Error> {
+    serde_yaml::to_string(object).map_err(|e| Error::serialize(filename, e))
+}
+
+/// A document of a YAML stream
+struct Document {
+    /// the bytes of the document content, document markers excluded
+    range: Range<usize>,
+    /// the (1-based) line where the document content starts
+    line: usize,
+}
+
+/// It locates the documents of a YAML stream, in the same order they are
+/// produced by `serde_yaml::Deserializer`. As for the YAML spec, `---` and
+/// `...` are document markers only at the beginning of a line, and a document
+/// is started either explicitly by `---` or implicitly by some content.
+fn locate_documents(stream: &str) -> Vec<Document> {
+    let is_marker = |line: &str, marker: &str| {
+        line.strip_prefix(marker)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+    };
+    let is_content = |line: &str| {
+        let line = line.trim();
+        !line.is_empty() && !line.starts_with('#')
+    };
+
+    let mut documents: Vec<Document> = vec![];
+    // start of the current document, with the line of its first content (if any)
+    let mut current: Option<(usize, Option<usize>)> = None;
+    let mut offset = 0;
+
+    for (i, line) in stream.split_inclusive('\n').enumerate() {
+        let line_number = i + 1;
 
-    let converted_object: T = serde_yaml::from_str(file_string).unwrap();
+        if is_marker(line, "---") || is_marker(line, "...") {
+            if let Some((start, content_line)) = current.take() {
+                documents.push(Document {
+                    range: start..offset,
+                    line: content_line.unwrap_or(line_number),
+                });
+            }
+
+            if is_marker(line, "---") {
+                // the document can start on the same line of its marker
+                let content_line = is_content(&line[3..]).then_some(line_number);
+                current = Some((offset + 3, content_line));
+            }
+        } else {
+            match &mut current {
+                Some((_, content_line @ None)) if is_content(line) => *content_line = Some(line_number),
+                None if is_content(line) && !line.starts_with('%') => current = Some((offset, Some(line_number))),
+                _ => {}
+            }
+        }
+
+        offset += line.len();
+    }
+
+    if let Some((start, content_line)) = current {
+        documents.push(Document {
+            range: start..offset,
+            line: content_line.unwrap_or(stream.lines().count().max(1)),
+        });
+    }
 
-    converted_object
+    documents
 }
 
-/// It takes a k8s manifest and split it into a vector whenever it founds '---' separator
-fn unpack(manifest: &String) -> Vec<String> {
-    /*
-        Let's split the manifest using "---"
-        as a separator
-    */
-    let split = manifest.split("---");
-    let vec = split.map(|x| x.to_owned()).collect();    
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    vec
+    fn contents<'a>(stream: &'a str, documents: &[Document]) -> Vec<&'a str> {
+        documents.iter().map(|d| stream[d.range.clone()].trim()).collect()
+    }
+
+    #[test]
+    fn it_locates_the_documents_of_a_stream() {
+        let stream = "\
+# leading comment
+kind: Service
+---
+kind: Deployment
+...
+--- # same line
+kind: ConfigMap
+";
+        let documents = locate_documents(stream);
+
+        assert_eq!(
+            contents(stream, &documents),
+            ["kind: Service", "kind: Deployment", "# same line\nkind: ConfigMap"]
+        );
+        assert_eq!(documents.iter().map(|d| d.line).collect::<Vec<_>>(), [2, 4, 7]);
+    }
+
+    #[test]
+    fn it_does_not_split_on_markers_inside_block_scalars() {
+        let stream = "\
+kind: ConfigMap
+data:
+  config.yaml: |
+    first: 1
+    ---
+    second: 2
+---
+kind: Service
+";
+        let documents = locate_documents(stream);
+
+        assert_eq!(documents.len(), 2);
+        assert!(contents(stream, &documents)[0].ends_with("    ---\n    second: 2"));
+        assert_eq!(documents[1].line, 8);
+        assert_eq!(documents.len(), serde_yaml::Deserializer::from_str(stream).count());
+    }
+
+    #[test]
+    fn it_reports_errors_at_their_line_in_the_stream() {
+        let stream = "\
+apiVersion: v1
+kind: Service
+metadata:
+  name: cart
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata: []
+";
+
+        match parse_documents(Path::new("stream.yaml"), stream) {
+            Err(Error::Parse { line: Some(line), .. }) => assert_eq!(line, 8),
+            other => panic!("unexpected result: {:?}", other.map(|m| m.len())),
+        }
+    }
 }